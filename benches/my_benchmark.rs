@@ -30,24 +30,43 @@ fn compare_algos(c: &mut Criterion) {
     group.bench_function("lut u16", |b| {
         b.iter(|| double_array_lookup_u16(black_box(&array)))
     });
+    group.bench_function("portable simd 16", |b| {
+        b.iter(|| double_array_portable_simd::<16>(black_box(&array)))
+    });
+    group.bench_function("portable simd 32", |b| {
+        b.iter(|| double_array_portable_simd::<32>(black_box(&array)))
+    });
+    group.bench_function("portable simd 64", |b| {
+        b.iter(|| double_array_portable_simd::<64>(black_box(&array)))
+    });
     group.bench_function("laura", |b| {
         b.iter(|| double_array_sisd_laura(black_box(&array)))
     });
     group.bench_function("laura u32", |b| {
         b.iter(|| double_array_sisd_laura_u32(black_box(&array)))
     });
-    #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "avx512f"
-    ))]
-    group.bench_function("lut u4 simd avx", |b| {
-        {
-            b.iter(|| double_array_lookup_avx_u4(black_box(&array)))
-        }
+    #[cfg(target_arch = "x86_64")]
+    group.bench_function("clmul", |b| {
+        b.iter(|| double_array_clmul(black_box(&array)))
+    });
+    #[cfg(target_arch = "x86_64")]
+    group.bench_function("pdep", |b| b.iter(|| double_array_pdep(black_box(&array))));
+    #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+    group.bench_function("lut u4 simd ssse3", |b| {
+        b.iter(|| double_array_lookup_ssse3_u4(black_box(&array)))
+    });
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    group.bench_function("lut u4 simd avx2", |b| {
+        b.iter(|| double_array_lookup_avx2_u4(black_box(&array)))
     });
     #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "avx512f"
+        target_arch = "x86_64",
+        target_feature = "avx512f",
+        target_feature = "avx512vl",
+        target_feature = "avx512bw",
+        target_feature = "avx512vbmi",
+        target_feature = "avx512vbmi2",
+        target_feature = "avx512bitalg"
     ))]
     group.bench_function("simd u4 simd avx512", |b| {
         {
@@ -55,8 +74,13 @@ fn compare_algos(c: &mut Criterion) {
         }
     });
     #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "avx512f"
+        target_arch = "x86_64",
+        target_feature = "avx512f",
+        target_feature = "avx512vl",
+        target_feature = "avx512bw",
+        target_feature = "avx512vbmi",
+        target_feature = "avx512vbmi2",
+        target_feature = "avx512bitalg"
     ))]
     group.bench_function("simd laura avx", |b| {
         {
@@ -64,8 +88,13 @@ fn compare_algos(c: &mut Criterion) {
         }
     });
     #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "avx512f"
+        target_arch = "x86_64",
+        target_feature = "avx512f",
+        target_feature = "avx512vl",
+        target_feature = "avx512bw",
+        target_feature = "avx512vbmi",
+        target_feature = "avx512vbmi2",
+        target_feature = "avx512bitalg"
     ))]
     group.bench_function("throughput test", |b| {
         {
@@ -90,25 +119,289 @@ fn compare_algos(c: &mut Criterion) {
     //         .unwrap();
     //     b.iter(|| double_array_lookup_neon_u4_multithread(black_box(&array), &thread_pool))
     // });
+    let aligned_array = AlignedBuf::from_slice(&array);
+    group.bench_function("aligned", |b| {
+        b.iter(|| double_bits_aligned(black_box(&aligned_array)))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| double_bits_parallel(black_box(&array), None))
+    });
     group.bench_function("ben", |b| b.iter(|| double_array_ben(black_box(&array))));
     group.bench_function("benk", |b| b.iter(|| double_array_benk(black_box(&array))));
     group.finish();
 }
 
-// fn compare_size_rayon(c: &mut Criterion) {
-//     static KB: usize = 1024;
-//     let mut group = c.benchmark_group("rayon");
-//     for size in [KB, 16 * KB, 128 * KB, KB * KB, 16 * KB * KB, 128 * KB * KB].iter() {
-//         let array = generate_array(*size);
-//         group.throughput(Throughput::Bytes(*size as u64));
-//         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &_size| {
-//             b.iter(|| double_array_sisd_opt_rayon(black_box(&array)))
-//         });
-//     }
-//     let plot_config = PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
-//     group.plot_config(plot_config);
-//     group.finish();
-// }
+fn compare_transpose(c: &mut Criterion) {
+    static KB: usize = 1024;
+    let mut group = c.benchmark_group("transpose");
+    // 1024 rows, so the output has as many bytes per row as the input has bits
+    let rows = 1024;
+    for row_bytes in [16, 128, KB].iter() {
+        let array = generate_array(rows * row_bytes);
+        group.throughput(Throughput::Bytes((rows * row_bytes) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("swar", row_bytes),
+            row_bytes,
+            |b, &row_bytes| b.iter(|| transpose_bits_swar(black_box(&array), rows, row_bytes)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("simd", row_bytes),
+            row_bytes,
+            |b, &row_bytes| b.iter(|| transpose_bits_simd(black_box(&array), rows, row_bytes)),
+        );
+        #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+        group.bench_with_input(
+            BenchmarkId::new("sse2", row_bytes),
+            row_bytes,
+            |b, &row_bytes| b.iter(|| transpose_bits_sse2(black_box(&array), rows, row_bytes)),
+        );
+        #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+        group.bench_with_input(
+            BenchmarkId::new("avx2", row_bytes),
+            row_bytes,
+            |b, &row_bytes| b.iter(|| transpose_bits_avx2(black_box(&array), rows, row_bytes)),
+        );
+    }
+    let plot_config = PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
+    group.plot_config(plot_config);
+    group.finish();
+}
+
+fn compare_bitplanes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bitplanes");
+    let x = 10 * 1024 * 1024;
+    let array = generate_array(x);
+    // input and output are the same size
+    group.throughput(Throughput::Bytes((x * 2) as u64));
+    for lanes in [8, 64, 1000].iter() {
+        group.bench_with_input(BenchmarkId::new("to", lanes), lanes, |b, &lanes| {
+            b.iter(|| to_bitplanes(black_box(&array[..x - x % lanes]), lanes))
+        });
+        let planes = to_bitplanes(&array[..x - x % lanes], *lanes);
+        group.bench_with_input(BenchmarkId::new("from", lanes), lanes, |b, &lanes| {
+            b.iter(|| from_bitplanes(black_box(&planes), lanes))
+        });
+    }
+    group.finish();
+}
+
+fn compare_reverse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reverse");
+    let x = 10 * 1024 * 1024;
+    let array = generate_array(x);
+    group.throughput(Throughput::Bytes((x * 2) as u64));
+    group.bench_function("bytes lut", |b| {
+        b.iter(|| reverse_bits_in_bytes_lookup(black_box(&array)))
+    });
+    group.bench_function("bytes", |b| {
+        b.iter(|| reverse_bits_in_bytes(black_box(&array)))
+    });
+    group.bench_function("u64 lut", |b| {
+        b.iter(|| reverse_bits_in_words_lookup::<u64>(black_box(&array)))
+    });
+    group.bench_function("u64", |b| {
+        b.iter(|| reverse_bits_in_words::<u64>(black_box(&array)))
+    });
+    group.bench_function("buffer lut", |b| {
+        b.iter(|| reverse_bits_buffer_lookup(black_box(&array)))
+    });
+    group.bench_function("buffer", |b| {
+        b.iter(|| reverse_bits_buffer(black_box(&array)))
+    });
+    group.finish();
+}
+
+fn compare_unpack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unpack");
+    let x = 1024 * 1024;
+    let array = generate_array(x);
+    let unpacked = unpack_bits(&array, 0xff);
+    group.throughput(Throughput::Bytes((x * 9) as u64));
+    group.bench_function("unpack sisd", |b| {
+        b.iter(|| unpack_bits_sisd(black_box(&array), 0xff))
+    });
+    group.bench_function("unpack", |b| {
+        b.iter(|| unpack_bits(black_box(&array), 0xff))
+    });
+    group.bench_function("pack sisd", |b| {
+        b.iter(|| pack_bits_sisd(black_box(&unpacked)))
+    });
+    group.bench_function("pack", |b| b.iter(|| pack_bits(black_box(&unpacked))));
+    group.finish();
+}
+
+fn compare_expand_depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("expand_depth");
+    let x = 1024 * 1024;
+    let array = generate_array(x);
+    for (from_bpp, to_bpp) in [(1, 2), (1, 8), (4, 8)] {
+        let palette: Vec<u8> = (0..1 << from_bpp)
+            .map(|i| (i % (1 << to_bpp)) as u8)
+            .collect();
+        let parameter = format!("{from_bpp}-{to_bpp}");
+        group.throughput(Throughput::Bytes((x + x * to_bpp / from_bpp) as u64));
+        group.bench_function(BenchmarkId::new("lut u4", &parameter), |b| {
+            b.iter(|| expand_depth_lookup_u4(black_box(&array), from_bpp, to_bpp, &palette))
+        });
+        group.bench_function(BenchmarkId::new("lut u8", &parameter), |b| {
+            b.iter(|| expand_depth_lookup_u8(black_box(&array), from_bpp, to_bpp, &palette))
+        });
+        #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+        group.bench_function(BenchmarkId::new("ssse3", &parameter), |b| {
+            b.iter(|| expand_depth_ssse3(black_box(&array), from_bpp, to_bpp, &palette))
+        });
+    }
+    group.finish();
+}
+
+fn compare_scale(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scale");
+    let x = 1024 * 1024;
+    let array = generate_array(x);
+    for n in [2, 3, 4, 8] {
+        group.throughput(Throughput::Bytes((x + x * n) as u64));
+        if n.is_multiple_of(2) {
+            group.bench_with_input(BenchmarkId::new("lut u4", n), &n, |b, &n| {
+                b.iter(|| multiply_array_lookup_u4(black_box(&array), n))
+            });
+        }
+        group.bench_with_input(BenchmarkId::new("lut u8", n), &n, |b, &n| {
+            b.iter(|| multiply_array_lookup_u8(black_box(&array), n))
+        });
+        group.bench_with_input(BenchmarkId::new("multiply_bits", n), &n, |b, &n| {
+            b.iter(|| multiply_bits(black_box(&array), n))
+        });
+    }
+    for (num, den) in [(3, 2), (5, 3), (2, 1)] {
+        group.throughput(Throughput::Bytes((x + x * num / den) as u64));
+        group.bench_function(BenchmarkId::new("resample", format!("{num}/{den}")), |b| {
+            b.iter(|| resample_bits(black_box(&array), x * 8, num, den))
+        });
+    }
+    // a 1024x1024 bitmap with non-square pixels
+    let (width, height, stride): (usize, usize, usize) = (1000, 1024, 128);
+    let bitmap = &array[..height * stride];
+    for (scale_x, scale_y) in [(2, 2), (2, 3), (3, 3), (4, 4)] {
+        let scaled_bytes = (width * scale_x).div_ceil(8) * height * scale_y;
+        group.throughput(Throughput::Bytes((bitmap.len() + scaled_bytes) as u64));
+        group.bench_function(
+            BenchmarkId::new("bitmap", format!("{scale_x}x{scale_y}")),
+            |b| b.iter(|| scale_bitmap(black_box(bitmap), width, height, stride, scale_x, scale_y)),
+        );
+    }
+    group.finish();
+}
+
+/// sequential against parallel doubling over the input size, to show where they cross
+fn compare_size_rayon(c: &mut Criterion) {
+    static KB: usize = 1024;
+    let mut group = c.benchmark_group("rayon");
+    // "auto" should switch where it pays off on this machine
+    calibrate_parallel_threshold();
+    for size in [KB, 16 * KB, 128 * KB, KB * KB, 16 * KB * KB, 128 * KB * KB].iter() {
+        let array = generate_array(*size);
+        group.throughput(Throughput::Bytes(*size as u64 * 3));
+        group.bench_with_input(BenchmarkId::new("sequential", size), size, |b, &_size| {
+            b.iter(|| double_bits_sequential(black_box(&array)))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", size), size, |b, &_size| {
+            b.iter(|| double_bits_parallel(black_box(&array), None))
+        });
+        group.bench_with_input(BenchmarkId::new("auto", size), size, |b, &_size| {
+            b.iter(|| double_bits(black_box(&array)))
+        });
+    }
+    let plot_config = PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
+    group.plot_config(plot_config);
+    group.finish();
+}
+
+/// 64 MiB through the io adapters against the pipeline with a growing number of workers
+fn compare_pipeline(c: &mut Criterion) {
+    let size = 64usize << 20;
+    let array = generate_array(size);
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(size as u64 * 3));
+    group.bench_function("writer", |b| {
+        b.iter(|| {
+            let mut writer = DoublingWriter::new(std::io::sink());
+            std::io::Write::write_all(&mut writer, black_box(&array)).unwrap();
+            writer.into_inner().unwrap()
+        })
+    });
+    group.bench_function("reader", |b| {
+        b.iter(|| {
+            std::io::copy(
+                &mut DoublingReader::new(black_box(&array[..])),
+                &mut std::io::sink(),
+            )
+        })
+    });
+    for workers in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("pipeline", workers),
+            &workers,
+            |b, &workers| {
+                b.iter(|| {
+                    Pipeline::new(black_box(&array[..]), std::io::sink())
+                        .workers(workers)
+                        .run()
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// regular against streaming stores, from sizes that fit into the cache to ones that are far bigger
+fn compare_store_mode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("store");
+    group.sample_size(10);
+    let mut sizes = vec![10usize << 20, 100 << 20];
+    // 4 GiB in and 8 GiB out per kernel, so only when asked for
+    if std::env::var_os("NEON_TEST_BIG_BENCH").is_some() {
+        sizes.extend([1 << 30, 4 << 30]);
+    }
+    // only the x86 kernels have a choice of stores
+    #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+    for size in sizes.iter() {
+        let array = generate_array(*size);
+        group.throughput(Throughput::Bytes(*size as u64 * 3));
+        for store in [StoreMode::Regular, StoreMode::Streaming] {
+            let name = format!("{store:?}");
+            group.bench_with_input(
+                BenchmarkId::new(format!("ssse3 {name}"), size),
+                size,
+                |b, _| b.iter(|| double_array_lookup_ssse3_u4_with(black_box(&array), store)),
+            );
+            #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+            group.bench_with_input(
+                BenchmarkId::new(format!("avx2 {name}"), size),
+                size,
+                |b, _| b.iter(|| double_array_lookup_avx2_u4_with(black_box(&array), store)),
+            );
+            #[cfg(all(
+                target_arch = "x86_64",
+                target_feature = "avx512f",
+                target_feature = "avx512vl",
+                target_feature = "avx512bw",
+                target_feature = "avx512vbmi",
+                target_feature = "avx512vbmi2",
+                target_feature = "avx512bitalg"
+            ))]
+            group.bench_with_input(
+                BenchmarkId::new(format!("avx512 {name}"), size),
+                size,
+                |b, _| b.iter(|| double_array_lookup_avx512_u4_with(black_box(&array), store)),
+            );
+        }
+    }
+    let plot_config = PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
+    group.plot_config(plot_config);
+    group.finish();
+}
 
 // fn compare_size_opt(c: &mut Criterion) {
 //     static KB: usize = 1024;
@@ -124,5 +417,17 @@ fn compare_algos(c: &mut Criterion) {
 // }
 
 // criterion_group!(benches, compare_size_rayon, compare_size_opt);
-criterion_group!(benches, compare_algos);
+criterion_group!(
+    benches,
+    compare_algos,
+    compare_size_rayon,
+    compare_pipeline,
+    compare_store_mode,
+    compare_transpose,
+    compare_bitplanes,
+    compare_reverse,
+    compare_unpack,
+    compare_expand_depth,
+    compare_scale
+);
 criterion_main!(benches);
@@ -0,0 +1,191 @@
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+// A `Vec<u8>` is only as aligned as the allocator makes it, so the SIMD kernels start with
+// a scalar prologue up to the first aligned vector. An `AlignedBuf` starts on a cache line
+// (or a huge page), so for one as input and output that prologue comes out empty.
+
+/// Alignment of `AlignedBuf::zeroed`, one cache line.
+pub const CACHE_LINE_ALIGN: usize = 64;
+/// Alignment of `AlignedBuf::zeroed_huge`, one transparent huge page on x86_64 and aarch64.
+pub const HUGE_PAGE_ALIGN: usize = 2 * 1024 * 1024;
+
+/// A fixed size, zero initialised byte buffer with an aligned start.
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    align: usize,
+    /// Mapped by `zeroed_huge` with `mmap` rather than taken from the allocator.
+    mapped: bool,
+}
+
+// SAFETY: the buffer is owned like a `Box<[u8]>`
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    /// `len` zero bytes, starting on a cache line.
+    pub fn zeroed(len: usize) -> Self {
+        Self::zeroed_aligned(len, CACHE_LINE_ALIGN)
+    }
+
+    /// `len` zero bytes, starting on a huge page. On Linux the buffer is mapped on its own and
+    /// the kernel is asked to back it with transparent huge pages before it is touched, which
+    /// saves TLB misses on buffers of many megabytes.
+    pub fn zeroed_huge(len: usize) -> Self {
+        #[cfg(target_os = "linux")]
+        if len > 0 {
+            return Self::mapped_huge(len);
+        }
+        Self::zeroed_aligned(len, HUGE_PAGE_ALIGN)
+    }
+
+    /// `zeroed_huge` on Linux: maps a huge page more than it needs, cuts the mapping down to
+    /// whole huge pages from the first aligned address, then gives the hint and touches it.
+    #[cfg(target_os = "linux")]
+    fn mapped_huge(len: usize) -> Self {
+        let mapped_len = len.next_multiple_of(HUGE_PAGE_ALIGN);
+        unsafe {
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                mapped_len + HUGE_PAGE_ALIGN,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                alloc::handle_alloc_error(Layout::from_size_align(len, HUGE_PAGE_ALIGN).unwrap());
+            }
+            let start = ptr as usize;
+            let head = start.next_multiple_of(HUGE_PAGE_ALIGN) - start;
+            let tail = HUGE_PAGE_ALIGN - head;
+            if head > 0 {
+                libc::munmap(ptr, head);
+            }
+            let ptr = (ptr as *mut u8).add(head);
+            if tail > 0 {
+                libc::munmap(ptr.add(mapped_len) as *mut libc::c_void, tail);
+            }
+            // only a hint, the buffer works the same without huge pages
+            libc::madvise(ptr as *mut libc::c_void, mapped_len, libc::MADV_HUGEPAGE);
+            // anonymous pages are zero already, touching them faults them in after the hint
+            for offset in (0..len).step_by(4096) {
+                ptr.add(offset).write_volatile(0);
+            }
+            AlignedBuf {
+                ptr: NonNull::new_unchecked(ptr),
+                len,
+                align: HUGE_PAGE_ALIGN,
+                mapped: true,
+            }
+        }
+    }
+
+    /// `len` zero bytes, starting at a multiple of `align`, which has to be a power of two.
+    pub fn zeroed_aligned(len: usize, align: usize) -> Self {
+        let ptr = if len == 0 {
+            // a dangling but aligned pointer, like an empty `Vec` has
+            NonNull::new(align as *mut u8).unwrap()
+        } else {
+            let layout = Layout::from_size_align(len, align).unwrap();
+            let ptr = unsafe { alloc::alloc_zeroed(layout) };
+            NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout))
+        };
+        AlignedBuf {
+            ptr,
+            len,
+            align,
+            mapped: false,
+        }
+    }
+
+    /// A copy of `data`, starting on a cache line.
+    pub fn from_slice(data: &[u8]) -> Self {
+        let mut buf = Self::zeroed(data.len());
+        buf.copy_from_slice(data);
+        buf
+    }
+
+    /// The alignment the buffer starts at.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        if self.mapped {
+            // the same whole huge pages `mapped_huge` kept
+            #[cfg(target_os = "linux")]
+            unsafe {
+                libc::munmap(
+                    self.ptr.as_ptr() as *mut libc::c_void,
+                    self.len.next_multiple_of(HUGE_PAGE_ALIGN),
+                );
+            }
+        } else if self.len > 0 {
+            let layout = Layout::from_size_align(self.len, self.align).unwrap();
+            unsafe { alloc::dealloc(self.ptr.as_ptr(), layout) };
+        }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Clone for AlignedBuf {
+    fn clone(&self) -> Self {
+        let mut buf = if self.mapped {
+            Self::zeroed_huge(self.len)
+        } else {
+            Self::zeroed_aligned(self.len, self.align)
+        };
+        buf.copy_from_slice(self);
+        buf
+    }
+}
+
+impl PartialEq for AlignedBuf {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for AlignedBuf {}
+
+impl fmt::Debug for AlignedBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AlignedBuf")
+            .field("len", &self.len)
+            .field("align", &self.align)
+            .finish()
+    }
+}
+
+/// `generate_array` in a buffer that starts on a cache line.
+pub fn generate_aligned_array(size: usize) -> AlignedBuf {
+    AlignedBuf::from_slice(&crate::generate_array(size))
+}
+
+/// Double up each bit of `array` into a new cache line aligned buffer with the fastest slice
+/// kernel available, with streaming stores if the output does not fit into the last level
+/// cache.
+pub fn double_bits_aligned(array: &[u8]) -> AlignedBuf {
+    let mut doubled_array = AlignedBuf::zeroed(array.len() * 2);
+    crate::double_bits_slice_with(array, &mut doubled_array, crate::StoreMode::Auto);
+    doubled_array
+}
@@ -0,0 +1,172 @@
+use crate::{multiply_bits, resample_bitmap};
+
+// 1bpp bitmaps are stored row by row, MSB-first, each row starting `stride` bytes
+// after the previous one. Only the first `width` bits of a row are pixels, the
+// padding bits after them can hold anything in the input and are zero in the output.
+
+/// Mask for the pixel bits in the last byte of a row that is `width` pixels wide.
+pub(crate) fn last_byte_mask(width: usize) -> u8 {
+    match width % 8 {
+        0 => 0xff,
+        bits => 0xff << (8 - bits),
+    }
+}
+
+/// Check that `array` holds `height` rows of `row_bytes` bytes, `stride` bytes apart.
+pub(crate) fn check_bitmap(array: &[u8], row_bytes: usize, height: usize, stride: usize) {
+    assert!(stride >= row_bytes);
+    if height > 0 {
+        assert!(array.len() >= (height - 1) * stride + row_bytes);
+    }
+}
+
+/// Collect the pixel bytes of every row into one contiguous buffer without padding bytes.
+pub(crate) fn pack_rows(array: &[u8], row_bytes: usize, height: usize, stride: usize) -> Vec<u8> {
+    check_bitmap(array, row_bytes, height, stride);
+    if stride == row_bytes {
+        return array[..height * row_bytes].to_vec();
+    }
+    let mut packed = Vec::with_capacity(height * row_bytes);
+    for row in 0..height {
+        packed.extend_from_slice(&array[row * stride..row * stride + row_bytes]);
+    }
+    packed
+}
+
+/// Scale a 1bpp bitmap to twice its width and height with nearest neighbour sampling.
+/// Returns the scaled bitmap and its stride, which is the smallest one that fits a row.
+pub fn upscale_bitmap_2x(
+    array: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+) -> (Vec<u8>, usize) {
+    scale_bitmap(array, width, height, stride, 2, 2)
+}
+
+/// Scale a 1bpp bitmap by `scale_x` horizontally and `scale_y` vertically with nearest
+/// neighbour sampling. Returns the scaled bitmap and its stride, which is the smallest one that fits a row.
+pub fn scale_bitmap(
+    array: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    scale_x: usize,
+    scale_y: usize,
+) -> (Vec<u8>, usize) {
+    assert!(scale_x > 0 && scale_y > 0);
+    let row_bytes = width.div_ceil(8);
+    let dst_stride = (width * scale_x).div_ceil(8);
+    let mask = last_byte_mask(width * scale_x);
+    if width == 0 {
+        return (Vec::new(), 0);
+    }
+
+    // scale all rows horizontally in one go, so the kernel sees one long slice
+    let multiplied = multiply_bits(&pack_rows(array, row_bytes, height, stride), scale_x);
+
+    let mut scaled = vec![0; dst_stride * height * scale_y];
+    for (multiplied_row, rows) in multiplied
+        .chunks_exact(row_bytes * scale_x)
+        .zip(scaled.chunks_exact_mut(dst_stride * scale_y))
+    {
+        let (first, rest) = rows.split_at_mut(dst_stride);
+        first.copy_from_slice(&multiplied_row[..dst_stride]);
+        first[dst_stride - 1] &= mask;
+        // the vertical axis is just copies of the first row
+        for row in rest.chunks_exact_mut(dst_stride) {
+            row.copy_from_slice(first);
+        }
+    }
+    (scaled, dst_stride)
+}
+
+/// An owned 1bpp bitmap, laid out like the slices the scaling functions take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl Bitmap {
+    /// An all zero bitmap with the smallest stride that fits a row.
+    /// Panics if it would not fit into memory.
+    pub fn new(width: usize, height: usize) -> Self {
+        let stride = width.div_ceil(8);
+        let len = stride.checked_mul(height).expect("bitmap too big");
+        Bitmap {
+            width,
+            height,
+            stride,
+            data: vec![0; len],
+        }
+    }
+
+    pub fn from_raw(data: Vec<u8>, width: usize, height: usize, stride: usize) -> Self {
+        check_bitmap(&data, width.div_ceil(8), height, stride);
+        Bitmap {
+            width,
+            height,
+            stride,
+            data,
+        }
+    }
+
+    /// The pixel bytes of row `y`, without the padding bytes up to the stride.
+    pub fn row(&self, y: usize) -> &[u8] {
+        &self.data[y * self.stride..y * self.stride + self.width.div_ceil(8)]
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        assert!(x < self.width && y < self.height);
+        (self.data[y * self.stride + x / 8] >> (7 - x % 8)) & 1 == 1
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: bool) {
+        assert!(x < self.width && y < self.height);
+        let byte = &mut self.data[y * self.stride + x / 8];
+        let bit = 0b1000_0000 >> (x % 8);
+        if value {
+            *byte |= bit;
+        } else {
+            *byte &= !bit;
+        }
+    }
+
+    /// See `upscale_bitmap_2x`.
+    pub fn upscale_2x(&self) -> Bitmap {
+        self.scale(2, 2)
+    }
+
+    /// See `scale_bitmap`.
+    pub fn scale(&self, scale_x: usize, scale_y: usize) -> Bitmap {
+        let (data, stride) = scale_bitmap(
+            &self.data,
+            self.width,
+            self.height,
+            self.stride,
+            scale_x,
+            scale_y,
+        );
+        Bitmap {
+            width: self.width * scale_x,
+            height: self.height * scale_y,
+            stride,
+            data,
+        }
+    }
+
+    /// See `resample_bitmap`.
+    pub fn resample(&self, x: (usize, usize), y: (usize, usize)) -> Bitmap {
+        let (data, stride) =
+            resample_bitmap(&self.data, self.width, self.height, self.stride, x, y);
+        Bitmap {
+            width: crate::resampled_len(self.width, x.0, x.1),
+            height: crate::resampled_len(self.height, y.0, y.1),
+            stride,
+            data,
+        }
+    }
+}
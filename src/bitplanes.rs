@@ -0,0 +1,76 @@
+use crate::transpose_bits;
+
+// Bit planes are stored step by step: every step of the input (one byte per lane)
+// takes `lanes.div_ceil(8)` bytes in each plane, lane 0 in the most significant bit.
+// Plane 0 holds the most significant bit of every byte, plane 7 the least significant one.
+
+lazy_static::lazy_static! {
+    /// Spreads the bits of a byte into the lowest bit of 8 bytes, MSB-first.
+    static ref SPREAD: [u64; 256] = {
+        let mut v = Vec::with_capacity(256);
+        for x in 0u64..=255 {
+            let mut res = 0u64;
+            for j in 0..8 {
+                let bit = (x >> j) & 1;
+                res |= bit << (j * 8);
+            }
+            v.push(res);
+        }
+        v.try_into().unwrap()
+    };
+}
+
+/// Split `lanes` interleaved byte streams into 8 bit planes.
+/// `array[step * lanes + lane]` is byte `step` of stream `lane`.
+pub fn to_bitplanes(array: &[u8], lanes: usize) -> [Vec<u8>; 8] {
+    assert!(lanes > 0);
+    assert_eq!(array.len() % lanes, 0);
+    let steps = array.len() / lanes;
+    let padded_lanes = lanes.div_ceil(8) * 8;
+    let plane_len = steps * padded_lanes / 8;
+
+    // transposing the whole input as a column of bytes gives one row per bit,
+    // which is already the plane layout if every step fills whole bytes
+    let transposed = if padded_lanes == lanes {
+        transpose_bits(array, array.len(), 1)
+    } else {
+        let mut padded = vec![0; steps * padded_lanes];
+        for (padded_step, step) in padded
+            .chunks_exact_mut(padded_lanes)
+            .zip(array.chunks_exact(lanes))
+        {
+            padded_step[..lanes].copy_from_slice(step);
+        }
+        transpose_bits(&padded, padded.len(), 1)
+    };
+
+    std::array::from_fn(|plane| transposed[plane * plane_len..(plane + 1) * plane_len].to_vec())
+}
+
+/// Inverse of `to_bitplanes`: merge 8 bit planes back into `lanes` interleaved byte streams.
+pub fn from_bitplanes(planes: &[Vec<u8>; 8], lanes: usize) -> Vec<u8> {
+    assert!(lanes > 0);
+    let plane_len = planes[0].len();
+    assert!(planes.iter().all(|plane| plane.len() == plane_len));
+    let lane_bytes = lanes.div_ceil(8);
+    assert_eq!(plane_len % lane_bytes, 0);
+    let padded_lanes = lane_bytes * 8;
+
+    let mut padded = vec![0; plane_len * 8];
+    for i in 0..plane_len {
+        let mut word = 0u64;
+        for (plane, bits) in planes.iter().enumerate() {
+            word |= SPREAD[bits[i] as usize] << (7 - plane);
+        }
+        padded[i * 8..i * 8 + 8].copy_from_slice(&word.to_be_bytes());
+    }
+
+    if padded_lanes == lanes {
+        return padded;
+    }
+    padded
+        .chunks_exact(padded_lanes)
+        .flat_map(|step| &step[..lanes])
+        .copied()
+        .collect()
+}
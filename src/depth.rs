@@ -0,0 +1,166 @@
+// Pixels are packed MSB-first like the doubled bits: the first pixel of a byte
+// sits in its most significant bits. Every source pixel value is used as an index
+// into the palette, which holds the destination value for it.
+//
+// Doubling is the special case `from_bpp = 1, to_bpp = 2, palette = [0, 3]`.
+
+fn check_depths(from_bpp: usize, to_bpp: usize, palette: &[u8]) {
+    assert!(matches!(from_bpp, 1 | 2 | 4), "from_bpp must be 1, 2 or 4");
+    assert!(matches!(to_bpp, 2 | 4 | 8), "to_bpp must be 2, 4 or 8");
+    assert!(to_bpp > from_bpp, "to_bpp must be larger than from_bpp");
+    assert_eq!(palette.len(), 1 << from_bpp);
+    assert!(
+        palette.iter().all(|&value| (value as u16) < 1 << to_bpp),
+        "palette value does not fit into to_bpp bits"
+    );
+}
+
+/// Expand the lowest `bits` bits of `value`, pixel by pixel, through the palette.
+/// The result has `bits / from_bpp * to_bpp` bits.
+fn expand_pixels(value: u32, bits: usize, from_bpp: usize, to_bpp: usize, palette: &[u8]) -> u64 {
+    let pixel_mask = (1 << from_bpp) - 1;
+    let mut res = 0u64;
+    for pixel in (0..bits / from_bpp).rev() {
+        let index = (value >> (pixel * from_bpp)) & pixel_mask;
+        res = res << to_bpp | palette[index as usize] as u64;
+    }
+    res
+}
+
+/// Expand the pixel depth of `array` with a 16 entry table per nibble.
+pub fn expand_depth_lookup_u4(
+    array: &[u8],
+    from_bpp: usize,
+    to_bpp: usize,
+    palette: &[u8],
+) -> Vec<u8> {
+    check_depths(from_bpp, to_bpp, palette);
+    // bytes of output per input nibble
+    let nibble_bytes = to_bpp / from_bpp / 2;
+
+    let lookup: [u32; 16] = std::array::from_fn(|nibble| {
+        expand_pixels(nibble as u32, 4, from_bpp, to_bpp, palette) as u32
+    });
+
+    let mut expanded = Vec::with_capacity(array.len() * nibble_bytes * 2);
+    for &x in array {
+        let high = lookup[(x >> 4) as usize].to_be_bytes();
+        let low = lookup[(x & 0b1111) as usize].to_be_bytes();
+        expanded.extend_from_slice(&high[4 - nibble_bytes..]);
+        expanded.extend_from_slice(&low[4 - nibble_bytes..]);
+    }
+    expanded
+}
+
+/// Expand the pixel depth of `array` with a 256 entry table per byte.
+pub fn expand_depth_lookup_u8(
+    array: &[u8],
+    from_bpp: usize,
+    to_bpp: usize,
+    palette: &[u8],
+) -> Vec<u8> {
+    check_depths(from_bpp, to_bpp, palette);
+    // bytes of output per input byte
+    let ratio = to_bpp / from_bpp;
+
+    let lookup: Vec<u64> = (0..=255u32)
+        .map(|x| expand_pixels(x, 8, from_bpp, to_bpp, palette))
+        .collect();
+
+    array
+        .iter()
+        .flat_map(|&x| lookup[x as usize].to_be_bytes().into_iter().skip(8 - ratio))
+        .collect()
+}
+
+/// Expand the pixel depth of `array` with byte shuffles, 16 input bytes at a time.
+///
+/// Every input byte is first replicated once per output byte. Each output byte then
+/// depends on at most 4 bits of its input byte, so it is looked up with `_mm_shuffle_epi8`
+/// from the nibble that holds those bits, using one 16 entry table per position in the nibble.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub fn expand_depth_ssse3(array: &[u8], from_bpp: usize, to_bpp: usize, palette: &[u8]) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    check_depths(from_bpp, to_bpp, palette);
+    // bytes of output per input byte, 2, 4 or 8
+    let ratio = to_bpp / from_bpp;
+    // output bytes per input nibble
+    let per_nibble = ratio / 2;
+    // input bits per output byte
+    let field_bits = 4 / per_nibble;
+
+    // tables[p][nibble] is the output byte for field p (from the top) of the nibble
+    let tables: Vec<[u8; 16]> = (0..per_nibble)
+        .map(|p| {
+            std::array::from_fn(|nibble| {
+                let field = (nibble >> (4 - (p + 1) * field_bits)) & ((1 << field_bits) - 1);
+                expand_pixels(field as u32, field_bits, from_bpp, to_bpp, palette) as u8
+            })
+        })
+        .collect();
+
+    let (array, rest) = array.split_at(array.len() - array.len() % 16);
+    let mut expanded: Vec<u8> = Vec::with_capacity((array.len() + rest.len()) * ratio);
+
+    unsafe {
+        let lookups: Vec<__m128i> = tables
+            .iter()
+            .map(|table| _mm_loadu_si128(table.as_ptr() as *const __m128i))
+            .collect();
+        // replication indices for each of the `ratio` output vectors
+        let replicate: Vec<__m128i> = (0..ratio)
+            .map(|v| {
+                let idx: [u8; 16] = std::array::from_fn(|k| ((v * 16 + k) / ratio) as u8);
+                _mm_loadu_si128(idx.as_ptr() as *const __m128i)
+            })
+            .collect();
+        // output bytes taken from the high nibble, the first half of each group
+        let high_mask: [u8; 16] =
+            std::array::from_fn(|k| if k % ratio < per_nibble { 0xff } else { 0 });
+        let high_mask = _mm_loadu_si128(high_mask.as_ptr() as *const __m128i);
+        // output bytes that use field p of their nibble
+        let field_masks: Vec<__m128i> = (0..per_nibble)
+            .map(|p| {
+                let mask: [u8; 16] =
+                    std::array::from_fn(|k| if k % per_nibble == p { 0xff } else { 0 });
+                _mm_loadu_si128(mask.as_ptr() as *const __m128i)
+            })
+            .collect();
+        let nibble_mask = _mm_set1_epi8(0x0f);
+
+        let mut_ptr = expanded.as_mut_ptr();
+        for (i, chunk) in array.chunks_exact(16).enumerate() {
+            let input = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            for (v, replicate) in replicate.iter().enumerate() {
+                let bytes = _mm_shuffle_epi8(input, *replicate);
+                let low = _mm_and_si128(bytes, nibble_mask);
+                let high = _mm_and_si128(_mm_srli_epi16(bytes, 4), nibble_mask);
+                let nibble = _mm_or_si128(
+                    _mm_and_si128(high_mask, high),
+                    _mm_andnot_si128(high_mask, low),
+                );
+                let mut output = _mm_setzero_si128();
+                for (lookup, field_mask) in lookups.iter().zip(&field_masks) {
+                    let field = _mm_shuffle_epi8(*lookup, nibble);
+                    output = _mm_or_si128(output, _mm_and_si128(*field_mask, field));
+                }
+                _mm_storeu_si128(mut_ptr.add((i * ratio + v) * 16) as *mut __m128i, output);
+            }
+        }
+        expanded.set_len(array.len() * ratio);
+    }
+
+    // deal with the rest of the array
+    expanded.extend(expand_depth_lookup_u8(rest, from_bpp, to_bpp, palette));
+    expanded
+}
+
+/// Expand the pixel depth of `array` from `from_bpp` (1, 2 or 4) to `to_bpp` (2, 4 or 8) bits per pixel.
+/// Every source pixel value `i` becomes `palette[i]`, so the palette needs `1 << from_bpp` entries.
+pub fn expand_depth(array: &[u8], from_bpp: usize, to_bpp: usize, palette: &[u8]) -> Vec<u8> {
+    #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+    return expand_depth_ssse3(array, from_bpp, to_bpp, palette);
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "ssse3")))]
+    return expand_depth_lookup_u8(array, from_bpp, to_bpp, palette);
+}
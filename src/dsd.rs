@@ -0,0 +1,380 @@
+use std::io::{self, Read, Write};
+
+use crate::formats::invalid_data;
+use crate::multiply_bits;
+
+// DSD audio is a 1-bit stream per channel. Each channel is kept here MSB-first,
+// the first sample in the most significant bit, which is what the kernels expect.
+// DSF stores samples LSB-first (usually) in blocks of 4096 bytes per channel,
+// DSDIFF (DFF) stores them MSB-first with the channels interleaved byte by byte.
+// Zero-order-hold upsampling by N repeats every sample N times.
+
+/// Bytes per channel block in DSF files.
+const DSF_BLOCK_SIZE: usize = 4096;
+
+/// DSD audio with one bitstream per channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsdAudio {
+    /// samples per second and channel, 2822400 for DSD64
+    pub sample_rate: u32,
+    /// samples per channel
+    pub sample_count: u64,
+    /// MSB-first samples of every channel, padded with zeros to whole bytes
+    pub channels: Vec<Vec<u8>>,
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len().saturating_sub(self.pos) < len {
+            return Err(invalid_data("unexpected end of DSD file"));
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+}
+
+impl DsdAudio {
+    /// Read a DSF or DFF file, depending on its magic bytes.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        match data.get(..4) {
+            Some(b"DSD ") => Self::parse_dsf(&data),
+            Some(b"FRM8") => Self::parse_dff(&data),
+            _ => Err(invalid_data("not a DSF or DFF file")),
+        }
+    }
+
+    pub fn read_dsf<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::parse_dsf(&data)
+    }
+
+    pub fn read_dff<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::parse_dff(&data)
+    }
+
+    fn parse_dsf(data: &[u8]) -> io::Result<Self> {
+        let mut cursor = Cursor { data, pos: 0 };
+        if cursor.bytes(4)? != b"DSD " {
+            return Err(invalid_data("not a DSF file"));
+        }
+        let header_size = u64::from_le_bytes(cursor.array()?) as usize;
+        cursor.pos = header_size;
+
+        if cursor.bytes(4)? != b"fmt " {
+            return Err(invalid_data("missing DSF fmt chunk"));
+        }
+        let fmt_size = u64::from_le_bytes(cursor.array()?) as usize;
+        if fmt_size < 52 {
+            return Err(invalid_data("DSF fmt chunk is too short"));
+        }
+        let fmt_start = cursor.pos;
+        let _version = u32::from_le_bytes(cursor.array()?);
+        if u32::from_le_bytes(cursor.array()?) != 0 {
+            return Err(invalid_data("unsupported DSF format id"));
+        }
+        let _channel_type = u32::from_le_bytes(cursor.array()?);
+        let channel_count = u32::from_le_bytes(cursor.array()?) as usize;
+        let sample_rate = u32::from_le_bytes(cursor.array()?);
+        let lsb_first = match u32::from_le_bytes(cursor.array()?) {
+            1 => true,
+            8 => false,
+            _ => return Err(invalid_data("unsupported DSF bits per sample")),
+        };
+        let sample_count = u64::from_le_bytes(cursor.array()?);
+        let block_size = u32::from_le_bytes(cursor.array()?) as usize;
+        if channel_count == 0 || block_size == 0 {
+            return Err(invalid_data("invalid DSF fmt chunk"));
+        }
+        // the chunk size counts its own id and size, which were read already
+        cursor.pos = fmt_start
+            .checked_add(fmt_size - 12)
+            .ok_or_else(|| invalid_data("DSF fmt chunk is too long"))?;
+
+        if cursor.bytes(4)? != b"data" {
+            return Err(invalid_data("missing DSF data chunk"));
+        }
+        let data_size = u64::from_le_bytes(cursor.array()?) as usize;
+        let samples = cursor.bytes(data_size.saturating_sub(12))?;
+
+        // blocks go round robin over the channels, the header is not trusted with an
+        // allocation bigger than the data
+        let channel_bytes = usize::try_from(sample_count.div_ceil(8))
+            .ok()
+            .filter(|&channel_bytes| {
+                channel_bytes
+                    .checked_mul(channel_count)
+                    .is_some_and(|bytes| bytes <= samples.len())
+            })
+            .ok_or_else(|| invalid_data("DSF data is too short"))?;
+        let mut channels = vec![Vec::with_capacity(channel_bytes); channel_count];
+        for (i, block) in samples.chunks(block_size).enumerate() {
+            channels[i % channel_count].extend_from_slice(block);
+        }
+        for channel in &mut channels {
+            if channel.len() < channel_bytes {
+                return Err(invalid_data("DSF data is too short"));
+            }
+            channel.truncate(channel_bytes);
+            if lsb_first {
+                for byte in channel.iter_mut() {
+                    *byte = byte.reverse_bits();
+                }
+            }
+            clear_padding(channel, sample_count);
+        }
+
+        Ok(DsdAudio {
+            sample_rate,
+            sample_count,
+            channels,
+        })
+    }
+
+    fn parse_dff(data: &[u8]) -> io::Result<Self> {
+        let mut cursor = Cursor { data, pos: 0 };
+        if cursor.bytes(4)? != b"FRM8" {
+            return Err(invalid_data("not a DFF file"));
+        }
+        let form_size = u64::from_be_bytes(cursor.array()?) as usize;
+        let form_end = cursor.pos.saturating_add(form_size);
+        if cursor.bytes(4)? != b"DSD " {
+            return Err(invalid_data("not a DSD form"));
+        }
+
+        let mut sample_rate = None;
+        let mut channel_count = None;
+        let mut samples = None;
+        while cursor.pos + 12 <= form_end {
+            let id = cursor.array::<4>()?;
+            let size = u64::from_be_bytes(cursor.array()?) as usize;
+            let chunk = cursor.bytes(size)?;
+            // chunks are padded to an even length
+            cursor.pos += size % 2;
+
+            match &id {
+                b"PROP" => {
+                    let mut prop = Cursor {
+                        data: chunk,
+                        pos: 0,
+                    };
+                    if prop.bytes(4)? != b"SND " {
+                        continue;
+                    }
+                    while prop.pos + 12 <= chunk.len() {
+                        let id = prop.array::<4>()?;
+                        let size = u64::from_be_bytes(prop.array()?) as usize;
+                        let sub = prop.bytes(size)?;
+                        prop.pos = usize::min(prop.pos + size % 2, chunk.len());
+                        let mut sub = Cursor { data: sub, pos: 0 };
+                        match &id {
+                            b"FS  " => sample_rate = Some(u32::from_be_bytes(sub.array()?)),
+                            b"CHNL" => {
+                                channel_count = Some(u16::from_be_bytes(sub.array()?) as usize)
+                            }
+                            b"CMPR" if sub.bytes(4)? != b"DSD " => {
+                                return Err(invalid_data("compressed DFF is not supported"));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                b"DSD " => samples = Some(chunk),
+                _ => {}
+            }
+        }
+
+        let sample_rate = sample_rate.ok_or_else(|| invalid_data("missing DFF sample rate"))?;
+        let channel_count = channel_count
+            .filter(|&count| count > 0)
+            .ok_or_else(|| invalid_data("missing DFF channels"))?;
+        let samples = samples.ok_or_else(|| invalid_data("missing DFF sound data"))?;
+        if samples.len() % channel_count != 0 {
+            return Err(invalid_data("DFF sound data does not fill every channel"));
+        }
+
+        // all channels together are as big as the data
+        let channel_bytes = samples.len() / channel_count;
+        let mut channels = vec![Vec::with_capacity(channel_bytes); channel_count];
+        for frame in samples.chunks_exact(channel_count) {
+            for (channel, &byte) in channels.iter_mut().zip(frame) {
+                channel.push(byte);
+            }
+        }
+
+        Ok(DsdAudio {
+            sample_rate,
+            sample_count: channel_bytes as u64 * 8,
+            channels,
+        })
+    }
+
+    /// Write a DSF file with LSB-first samples.
+    pub fn write_dsf<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let channel_count = self.channels.len();
+        let channel_bytes = self.sample_count.div_ceil(8) as usize;
+        let blocks = channel_bytes.div_ceil(DSF_BLOCK_SIZE);
+        let data_size = blocks * DSF_BLOCK_SIZE * channel_count;
+        let channel_type: u32 = match channel_count {
+            1..=3 => channel_count as u32,
+            4 => 4,
+            5 => 6,
+            6 => 7,
+            _ => return Err(invalid_data("DSF supports 1 to 6 channels")),
+        };
+
+        writer.write_all(b"DSD ")?;
+        writer.write_all(&28u64.to_le_bytes())?;
+        writer.write_all(&((28 + 52 + 12 + data_size) as u64).to_le_bytes())?;
+        // no metadata chunk
+        writer.write_all(&0u64.to_le_bytes())?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&52u64.to_le_bytes())?;
+        writer.write_all(&1u32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&channel_type.to_le_bytes())?;
+        writer.write_all(&(channel_count as u32).to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        // 1 bit per sample means LSB-first
+        writer.write_all(&1u32.to_le_bytes())?;
+        writer.write_all(&self.sample_count.to_le_bytes())?;
+        writer.write_all(&(DSF_BLOCK_SIZE as u32).to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&((12 + data_size) as u64).to_le_bytes())?;
+        let mut block = vec![0u8; DSF_BLOCK_SIZE];
+        for i in 0..blocks {
+            for channel in &self.channels {
+                let start = usize::min(i * DSF_BLOCK_SIZE, channel.len());
+                let end = usize::min(start + DSF_BLOCK_SIZE, channel.len());
+                block.fill(0);
+                for (dst, src) in block.iter_mut().zip(&channel[start..end]) {
+                    *dst = src.reverse_bits();
+                }
+                writer.write_all(&block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an uncompressed DFF file.
+    pub fn write_dff<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let channel_count = self.channels.len();
+        let channel_bytes = self.sample_count.div_ceil(8) as usize;
+        let data_size = channel_bytes * channel_count;
+        let compression_name = b"not compressed";
+        let cmpr_size = 4 + 1 + compression_name.len();
+        let chnl_size = 2 + 4 * channel_count;
+        let prop_size = 4 + (12 + 4) + (12 + chnl_size) + (12 + cmpr_size + cmpr_size % 2);
+        let form_size = 4 + (12 + 4) + (12 + prop_size) + (12 + data_size + data_size % 2);
+
+        writer.write_all(b"FRM8")?;
+        writer.write_all(&(form_size as u64).to_be_bytes())?;
+        writer.write_all(b"DSD ")?;
+
+        writer.write_all(b"FVER")?;
+        writer.write_all(&4u64.to_be_bytes())?;
+        writer.write_all(&0x0105_0000u32.to_be_bytes())?;
+
+        writer.write_all(b"PROP")?;
+        writer.write_all(&(prop_size as u64).to_be_bytes())?;
+        writer.write_all(b"SND ")?;
+        writer.write_all(b"FS  ")?;
+        writer.write_all(&4u64.to_be_bytes())?;
+        writer.write_all(&self.sample_rate.to_be_bytes())?;
+        writer.write_all(b"CHNL")?;
+        writer.write_all(&(chnl_size as u64).to_be_bytes())?;
+        writer.write_all(&(channel_count as u16).to_be_bytes())?;
+        for i in 0..channel_count {
+            match (channel_count, i) {
+                (2, 0) => writer.write_all(b"SLFT")?,
+                (2, 1) => writer.write_all(b"SRGT")?,
+                _ => write!(writer, "C{:03}", i)?,
+            }
+        }
+        writer.write_all(b"CMPR")?;
+        writer.write_all(&(cmpr_size as u64).to_be_bytes())?;
+        writer.write_all(b"DSD ")?;
+        writer.write_all(&[compression_name.len() as u8])?;
+        writer.write_all(compression_name)?;
+        writer.write_all(&vec![0; cmpr_size % 2])?;
+
+        writer.write_all(b"DSD ")?;
+        writer.write_all(&(data_size as u64).to_be_bytes())?;
+        let mut frame = vec![0u8; channel_count];
+        for i in 0..channel_bytes {
+            for (dst, channel) in frame.iter_mut().zip(&self.channels) {
+                *dst = channel.get(i).copied().unwrap_or(0);
+            }
+            writer.write_all(&frame)?;
+        }
+        writer.write_all(&vec![0; data_size % 2])?;
+        Ok(())
+    }
+
+    /// Upsample by repeating every sample `factor` times, 2 turns DSD64 into DSD128, 4 into DSD256.
+    /// Fails with `InvalidInput` for a zero `factor`, a sample rate or count that would overflow,
+    /// or a channel shorter than `sample_count` says.
+    pub fn upsample(&self, factor: usize) -> io::Result<DsdAudio> {
+        if factor == 0 {
+            return Err(invalid_input("cannot upsample by zero"));
+        }
+        let sample_rate = u32::try_from(factor)
+            .ok()
+            .and_then(|factor| self.sample_rate.checked_mul(factor))
+            .ok_or_else(|| invalid_input("upsampled sample rate is too high"))?;
+        let too_many = || invalid_input("upsampled sample count is too high");
+        let sample_count = self
+            .sample_count
+            .checked_mul(factor as u64)
+            .ok_or_else(too_many)?;
+        let bytes = |count: u64| usize::try_from(count.div_ceil(8)).map_err(|_| too_many());
+        let channel_bytes = bytes(self.sample_count)?;
+        let upsampled_bytes = bytes(sample_count)?;
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| {
+                let channel = channel
+                    .get(..channel_bytes)
+                    .ok_or_else(|| invalid_input("channel is shorter than the sample count"))?;
+                let mut upsampled = multiply_bits(channel, factor);
+                // padding bits of the last byte were repeated as well
+                upsampled.truncate(upsampled_bytes);
+                clear_padding(&mut upsampled, sample_count);
+                Ok(upsampled)
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(DsdAudio {
+            sample_rate,
+            sample_count,
+            channels,
+        })
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Zero the bits after the last sample.
+fn clear_padding(channel: &mut [u8], sample_count: u64) {
+    if let Some(last) = channel.last_mut() {
+        *last &= crate::bitmap::last_byte_mask(sample_count as usize);
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use memmap2::{Mmap, MmapMut};
+use rayon::ThreadPool;
+
+use crate::formats::invalid_data;
+use crate::parallel::auto_store_kernel;
+use crate::{double_bits_parallel_into_with, halve_bits_parallel_into_with};
+
+// Files are mapped into memory and processed in place by the parallel driver, with chunks of
+// whole pages so no two threads ever write to the same page of the output. If a file cannot
+// be mapped (pipes, some network file systems), it is streamed through buffers instead.
+
+/// Bytes per page, chunks handed to the parallel driver are a multiple of it.
+const PAGE_SIZE: usize = 4096;
+
+/// How `double_file` and `halve_file` go about it.
+#[derive(Debug, Clone, Copy)]
+pub struct FileOptions<'a> {
+    /// Try memory mapping both files before falling back to buffered I/O.
+    pub mmap: bool,
+    /// Flush the output to disk (`msync` or `fsync`) before returning.
+    pub sync: bool,
+    /// Input bytes per read in the buffered fallback.
+    pub buffer_size: usize,
+    /// Pool for the parallel kernel, the global one if there is none.
+    pub thread_pool: Option<&'a ThreadPool>,
+}
+
+impl Default for FileOptions<'_> {
+    fn default() -> Self {
+        FileOptions {
+            mmap: true,
+            sync: false,
+            buffer_size: 16 * 1024 * 1024,
+            thread_pool: None,
+        }
+    }
+}
+
+/// Which way a file is transformed.
+#[derive(Clone, Copy)]
+enum Direction {
+    Double,
+    Halve,
+}
+
+impl Direction {
+    fn output_len(self, input_len: u64) -> io::Result<u64> {
+        match self {
+            Direction::Double => input_len
+                .checked_mul(2)
+                .ok_or_else(|| invalid_data("file too big to double")),
+            Direction::Halve if input_len.is_multiple_of(2) => Ok(input_len / 2),
+            Direction::Halve => Err(invalid_data("doubled file has an odd length")),
+        }
+    }
+
+    fn run(self, input: &[u8], output: &mut [u8], thread_pool: Option<&ThreadPool>) {
+        match self {
+            Direction::Double => {
+                let kernel = auto_store_kernel(output.len());
+                double_bits_parallel_into_with(input, output, PAGE_SIZE, kernel, thread_pool)
+            }
+            Direction::Halve => {
+                halve_bits_parallel_into_with(input, output, PAGE_SIZE, thread_pool)
+            }
+        }
+    }
+}
+
+/// Double up each bit of the file at `input` into a new file at `output`, twice as long.
+pub fn double_file(input: &Path, output: &Path, options: &FileOptions) -> io::Result<()> {
+    transform_file(input, output, options, Direction::Double)
+}
+
+/// Halve each pair of bits of the file at `input`, see `halve_bits`, into a new file at `output`,
+/// half as long. Fails if the input has an odd length.
+pub fn halve_file(input: &Path, output: &Path, options: &FileOptions) -> io::Result<()> {
+    transform_file(input, output, options, Direction::Halve)
+}
+
+fn transform_file(
+    input: &Path,
+    output: &Path,
+    options: &FileOptions,
+    direction: Direction,
+) -> io::Result<()> {
+    // truncating the output would wipe the input before it is read
+    if is_same_file(input, output) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "input and output are the same file",
+        ));
+    }
+    let input = File::open(input)?;
+    let input_len = input.metadata()?.len();
+    let output_len = direction.output_len(input_len)?;
+    let output = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output)?;
+
+    // mapping an empty file fails, and there is nothing to do anyway
+    if options.mmap && input_len > 0 {
+        // only a failed mapping falls back, errors after it are the caller's
+        if let Ok((input_map, mut output_map)) = map_files(&input, &output, output_len) {
+            direction.run(&input_map, &mut output_map, options.thread_pool);
+            if options.sync {
+                output_map.flush()?;
+            }
+            return Ok(());
+        }
+        // start over, the failed mapping may have sized the output already
+        output.set_len(0)?;
+    }
+    transform_buffered(&input, &output, options, direction)
+}
+
+/// Whether `input` and `output` name the same file, through links or not.
+#[cfg(unix)]
+fn is_same_file(input: &Path, output: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    // an output that does not exist yet cannot be the input
+    match (std::fs::metadata(input), std::fs::metadata(output)) {
+        (Ok(input), Ok(output)) => (input.dev(), input.ino()) == (output.dev(), output.ino()),
+        _ => false,
+    }
+}
+
+/// Whether `input` and `output` name the same file, through links or not.
+#[cfg(not(unix))]
+fn is_same_file(input: &Path, output: &Path) -> bool {
+    // an output that does not exist yet cannot be the input
+    match (input.canonicalize(), output.canonicalize()) {
+        (Ok(input), Ok(output)) => input == output,
+        _ => false,
+    }
+}
+
+/// Map `input` and `output`, sized to `output_len` bytes.
+fn map_files(input: &File, output: &File, output_len: u64) -> io::Result<(Mmap, MmapMut)> {
+    let input_map = unsafe { Mmap::map(input)? };
+    output.set_len(output_len)?;
+    let output_map = unsafe { MmapMut::map_mut(output)? };
+    Ok((input_map, output_map))
+}
+
+fn transform_buffered(
+    input: &File,
+    output: &File,
+    options: &FileOptions,
+    direction: Direction,
+) -> io::Result<()> {
+    // whole pairs of bytes per read, so halving never splits one
+    let buffer_size = options.buffer_size.max(2) & !1;
+    let mut reader = input;
+    let mut writer = output;
+    let mut buffer = vec![0; buffer_size];
+    let mut transformed = Vec::new();
+    loop {
+        let len = read_full(&mut reader, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        let output_len = direction.output_len(len as u64)? as usize;
+        transformed.resize(output_len, 0);
+        direction.run(&buffer[..len], &mut transformed, options.thread_pool);
+        writer.write_all(&transformed)?;
+    }
+    if options.sync {
+        output.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Fill `buffer` as far as the reader goes, returns how much it got.
+pub(crate) fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
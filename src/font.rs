@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::formats::invalid_data;
+use crate::Bitmap;
+
+// Glyph bitmaps in BDF are hex rows, MSB-first and padded to whole bytes,
+// so they are loaded straight into a `Bitmap`. Offsets are relative to the
+// origin on the baseline, with y pointing up.
+
+/// One glyph of a BDF font.
+#[derive(Debug, Clone)]
+pub struct Glyph {
+    pub bitmap: Bitmap,
+    /// offset of the bitmap's left edge from the origin
+    pub x_offset: i32,
+    /// offset of the bitmap's bottom edge from the baseline
+    pub y_offset: i32,
+    /// distance to the origin of the next glyph
+    pub advance: i32,
+}
+
+/// A bitmap font loaded from a BDF file.
+#[derive(Debug, Clone)]
+pub struct BdfFont {
+    pub glyphs: HashMap<u32, Glyph>,
+    /// pixels above the baseline
+    pub ascent: i32,
+    /// pixels below the baseline
+    pub descent: i32,
+    /// glyph used for characters the font does not have
+    pub default_char: Option<u32>,
+}
+
+fn numbers<const N: usize>(words: &[&str]) -> io::Result<[i32; N]> {
+    let mut res = [0; N];
+    for (i, number) in res.iter_mut().enumerate() {
+        *number = words
+            .get(i)
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| invalid_data("invalid number in BDF file"))?;
+    }
+    Ok(res)
+}
+
+impl BdfFont {
+    /// Parse a BDF font. Glyphs without an encoding (`ENCODING -1`) are skipped.
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut glyphs = HashMap::new();
+        let mut bounding_box = [0; 4];
+        let mut ascent = None;
+        let mut descent = None;
+        let mut default_char = None;
+
+        let mut encoding = None;
+        let mut advance = 0;
+        let mut bbx = [0; 4];
+        let mut rows: Option<Vec<u8>> = None;
+
+        for line in reader.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((&keyword, args)) = words.split_first() else {
+                continue;
+            };
+
+            if let Some(rows) = rows.as_mut() {
+                if keyword != "ENDCHAR" {
+                    for pair in keyword.as_bytes().chunks(2) {
+                        let byte = std::str::from_utf8(pair)
+                            .ok()
+                            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                            .ok_or_else(|| invalid_data("invalid BDF bitmap row"))?;
+                        rows.push(byte);
+                    }
+                    continue;
+                }
+            }
+
+            match keyword {
+                "FONTBOUNDINGBOX" => bounding_box = numbers(args)?,
+                "FONT_ASCENT" => ascent = Some(numbers::<1>(args)?[0]),
+                "FONT_DESCENT" => descent = Some(numbers::<1>(args)?[0]),
+                "DEFAULT_CHAR" => default_char = Some(numbers::<1>(args)?[0] as u32),
+                "STARTCHAR" => {
+                    encoding = None;
+                    advance = bounding_box[0];
+                    bbx = bounding_box;
+                }
+                "ENCODING" => encoding = u32::try_from(numbers::<1>(args)?[0]).ok(),
+                "DWIDTH" => advance = numbers::<1>(args)?[0],
+                "BBX" => bbx = numbers(args)?,
+                "BITMAP" => rows = Some(Vec::new()),
+                "ENDCHAR" => {
+                    let data = rows
+                        .take()
+                        .ok_or_else(|| invalid_data("ENDCHAR without BITMAP"))?;
+                    let [width, height, x_offset, y_offset] = bbx;
+                    if width < 0 || height < 0 {
+                        return Err(invalid_data("negative BDF glyph size"));
+                    }
+                    let (width, height) = (width as usize, height as usize);
+                    let stride = width.div_ceil(8);
+                    if data.len() != stride * height {
+                        return Err(invalid_data("BDF bitmap does not match its BBX"));
+                    }
+                    if let Some(encoding) = encoding {
+                        let mut bitmap = Bitmap::from_raw(data, width, height, stride);
+                        if stride > 0 {
+                            let mask = crate::bitmap::last_byte_mask(width);
+                            for y in 0..height {
+                                bitmap.data[y * stride + stride - 1] &= mask;
+                            }
+                        }
+                        glyphs.insert(
+                            encoding,
+                            Glyph {
+                                bitmap,
+                                x_offset,
+                                y_offset,
+                                advance,
+                            },
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if rows.is_some() {
+            return Err(invalid_data("unterminated BDF glyph"));
+        }
+        // without the properties, fall back to the font bounding box
+        let ascent = ascent.unwrap_or(bounding_box[1] + bounding_box[3]);
+        let descent = descent.unwrap_or(-bounding_box[3]);
+        Ok(BdfFont {
+            glyphs,
+            ascent,
+            descent,
+            default_char,
+        })
+    }
+
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&(c as u32))
+            .or_else(|| self.glyphs.get(&self.default_char?))
+    }
+}
+
+/// OR the first `width` bits of `row` into `dst`, starting at bit `x`.
+fn blit_row(dst: &mut [u8], x: usize, row: &[u8], width: usize) {
+    let shift = x % 8;
+    let mask = crate::bitmap::last_byte_mask(width);
+    for (i, &byte) in row.iter().enumerate() {
+        let byte = if i + 1 == row.len() {
+            byte & mask
+        } else {
+            byte
+        };
+        let pos = x / 8 + i;
+        dst[pos] |= byte >> shift;
+        if shift > 0 && byte << (8 - shift) != 0 {
+            dst[pos + 1] |= byte << (8 - shift);
+        }
+    }
+}
+
+/// Render one line of text into a 1bpp bitmap, scaled up by `scale` in both directions.
+/// The bitmap is `(ascent + descent) * scale` rows high, characters the font does not have
+/// are left out.
+pub fn render_text(font: &BdfFont, text: &str, scale: usize) -> Bitmap {
+    let glyphs: Vec<&Glyph> = text.chars().filter_map(|c| font.glyph(c)).collect();
+
+    // find the horizontal extent first, glyphs can reach left of their origin
+    let mut pen = 0i32;
+    let mut left = 0i32;
+    let mut right = 0i32;
+    for glyph in &glyphs {
+        left = left.min(pen + glyph.x_offset);
+        right = right.max(pen + glyph.x_offset + glyph.bitmap.width as i32);
+        pen += glyph.advance;
+        right = right.max(pen);
+    }
+
+    let height = (font.ascent + font.descent).max(0) as usize;
+    let mut line = Bitmap::new((right - left) as usize, height);
+
+    let mut pen = -left;
+    for glyph in glyphs {
+        let x = (pen + glyph.x_offset) as usize;
+        // top row of the glyph, counted from the top of the line
+        let top = font.ascent - glyph.y_offset - glyph.bitmap.height as i32;
+        for y in 0..glyph.bitmap.height {
+            let line_y = top + y as i32;
+            if line_y < 0 || line_y >= height as i32 {
+                continue;
+            }
+            let start = line_y as usize * line.stride;
+            blit_row(
+                &mut line.data[start..start + line.stride],
+                x,
+                glyph.bitmap.row(y),
+                glyph.bitmap.width,
+            );
+        }
+        pen += glyph.advance;
+    }
+
+    if scale == 1 {
+        line
+    } else {
+        line.scale(scale, scale)
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::{self, Read, Write};
+
+use crate::Bitmap;
+
+// PBM stores pixels MSB-first with 1 as black, exactly like `Bitmap`.
+// XBM stores the first pixel of a byte in its least significant bit instead.
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Splits the PBM header and ASCII data into tokens, skipping whitespace and comments.
+struct PbmTokens<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PbmTokens<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'#' => {
+                    while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn token(&mut self) -> io::Result<&'a [u8]> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.pos < self.data.len()
+            && !self.data[self.pos].is_ascii_whitespace()
+            && self.data[self.pos] != b'#'
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(invalid_data("unexpected end of PBM header"));
+        }
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn number(&mut self) -> io::Result<usize> {
+        std::str::from_utf8(self.token()?)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid_data("invalid number in PBM header"))
+    }
+}
+
+/// Read a plain (P1) or raw (P4) PBM image.
+pub fn read_pbm<R: Read>(mut reader: R) -> io::Result<Bitmap> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut tokens = PbmTokens {
+        data: &data,
+        pos: 0,
+    };
+    let magic = tokens.token()?;
+    let raw = match magic {
+        b"P1" => false,
+        b"P4" => true,
+        _ => return Err(invalid_data("not a P1 or P4 PBM image")),
+    };
+    let width = tokens.number()?;
+    let height = tokens.number()?;
+    // an empty side needs no data at all, which bounds nothing about the other one
+    if (width == 0) != (height == 0) {
+        return Err(invalid_data("PBM image with only one empty side"));
+    }
+    // exactly one whitespace byte separates the header from the pixels
+    let start = tokens.pos + 1;
+    let available = data.len().saturating_sub(start);
+    // the header is not trusted with an allocation bigger than the file
+    let pixels = if raw {
+        width.div_ceil(8).checked_mul(height)
+    } else {
+        // at least one character per pixel
+        width.checked_mul(height)
+    };
+    if pixels.is_none_or(|pixels| pixels > available) {
+        return Err(invalid_data("PBM pixel data is too short"));
+    }
+    let mut bitmap = Bitmap::new(width, height);
+
+    if raw {
+        let end = start + bitmap.data.len();
+        bitmap.data.copy_from_slice(&data[start..end]);
+        clear_padding(&mut bitmap);
+    } else {
+        for y in 0..height {
+            for x in 0..width {
+                tokens.skip_whitespace();
+                match data.get(tokens.pos) {
+                    Some(b'0') => {}
+                    Some(b'1') => bitmap.set(x, y, true),
+                    _ => return Err(invalid_data("invalid or missing PBM pixel")),
+                }
+                tokens.pos += 1;
+            }
+        }
+    }
+    Ok(bitmap)
+}
+
+/// Write a raw (P4) PBM image.
+pub fn write_pbm<W: Write>(mut writer: W, bitmap: &Bitmap) -> io::Result<()> {
+    write!(writer, "P4\n{} {}\n", bitmap.width, bitmap.height)?;
+    let mask = crate::bitmap::last_byte_mask(bitmap.width);
+    for y in 0..bitmap.height {
+        let row = bitmap.row(y);
+        if let Some((last, row)) = row.split_last() {
+            writer.write_all(row)?;
+            writer.write_all(&[last & mask])?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a plain (P1) PBM image, 70 characters per line at most.
+pub fn write_pbm_ascii<W: Write>(mut writer: W, bitmap: &Bitmap) -> io::Result<()> {
+    write!(writer, "P1\n{} {}\n", bitmap.width, bitmap.height)?;
+    for y in 0..bitmap.height {
+        let mut line = Vec::with_capacity(bitmap.width + bitmap.width / 70 + 1);
+        for x in 0..bitmap.width {
+            if x > 0 && x % 70 == 0 {
+                line.push(b'\n');
+            }
+            line.push(if bitmap.get(x, y) { b'1' } else { b'0' });
+        }
+        line.push(b'\n');
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// Read an XBM image. The name in front of `_width`, `_height` and `_bits` is ignored.
+pub fn read_xbm<R: Read>(mut reader: R) -> io::Result<Bitmap> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let define = |suffix: &str| {
+        text.lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                (words.next()? == "#define").then_some(())?;
+                let name = words.next()?;
+                name.ends_with(suffix).then_some(())?;
+                words.next()?.parse::<usize>().ok()
+            })
+            .next()
+            .ok_or_else(|| invalid_data("missing XBM size"))
+    };
+    let width = define("_width")?;
+    let height = define("_height")?;
+    // an empty side needs no data at all, which bounds nothing about the other one
+    if (width == 0) != (height == 0) {
+        return Err(invalid_data("XBM image with only one empty side"));
+    }
+
+    let start = text
+        .find('{')
+        .ok_or_else(|| invalid_data("missing XBM data"))?;
+    let end = text[start..]
+        .find('}')
+        .ok_or_else(|| invalid_data("unterminated XBM data"))?;
+    let values = text[start + 1..start + end]
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            let parsed = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => value.parse(),
+            };
+            parsed.map_err(|_| invalid_data("invalid XBM byte"))
+        })
+        .collect::<io::Result<Vec<u8>>>()?;
+
+    let bytes = width.div_ceil(8).checked_mul(height);
+    if bytes.is_none_or(|bytes| bytes > values.len()) {
+        return Err(invalid_data("XBM data is too short"));
+    }
+    let mut bitmap = Bitmap::new(width, height);
+    for (byte, value) in bitmap.data.iter_mut().zip(values) {
+        *byte = value.reverse_bits();
+    }
+    clear_padding(&mut bitmap);
+    Ok(bitmap)
+}
+
+/// Write an XBM image with the variables prefixed by `name`.
+pub fn write_xbm<W: Write>(mut writer: W, bitmap: &Bitmap, name: &str) -> io::Result<()> {
+    writeln!(writer, "#define {name}_width {}", bitmap.width)?;
+    writeln!(writer, "#define {name}_height {}", bitmap.height)?;
+    write!(writer, "static unsigned char {name}_bits[] = {{")?;
+    let mask = crate::bitmap::last_byte_mask(bitmap.width);
+    let row_bytes = bitmap.width.div_ceil(8);
+    let mut count = 0;
+    for y in 0..bitmap.height {
+        for (i, &byte) in bitmap.row(y).iter().enumerate() {
+            let byte = if i + 1 == row_bytes {
+                byte & mask
+            } else {
+                byte
+            };
+            let separator = if count == 0 { "" } else { "," };
+            let indent = if count % 12 == 0 { "\n   " } else { "" };
+            write!(writer, "{separator}{indent} 0x{:02x}", byte.reverse_bits())?;
+            count += 1;
+        }
+    }
+    writeln!(writer, " }};")
+}
+
+/// Zero the bits after the last pixel of every row.
+fn clear_padding(bitmap: &mut Bitmap) {
+    let row_bytes = bitmap.width.div_ceil(8);
+    if row_bytes == 0 {
+        return;
+    }
+    let mask = crate::bitmap::last_byte_mask(bitmap.width);
+    for y in 0..bitmap.height {
+        bitmap.data[y * bitmap.stride + row_bytes - 1] &= mask;
+    }
+}
@@ -0,0 +1,60 @@
+// Halving undoes doubling: every pair of bits becomes one bit. Only the first bit of a pair
+// is looked at, so a doubled buffer always halves back to what it was doubled from.
+
+/// Keep the first bit of every pair of bits in `x`, the 64 bits become 32.
+fn halve_u64(x: u64) -> u32 {
+    let x = (x >> 1) & 0x5555_5555_5555_5555;
+    let x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    let x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    let x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    let x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    (x | (x >> 16)) as u32
+}
+
+/// Halve `doubled_array` into `array`, which is half as long.
+pub fn halve_bits_slice(doubled_array: &[u8], array: &mut [u8]) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+
+    let doubled_chunks = doubled_array.chunks_exact(8);
+    let doubled_rest = doubled_chunks.remainder();
+    let mut chunks = array.chunks_exact_mut(4);
+    for (doubled, halved) in doubled_chunks.zip(&mut chunks) {
+        let x = u64::from_be_bytes(doubled.try_into().unwrap());
+        halved.copy_from_slice(&halve_u64(x).to_be_bytes());
+    }
+    for (doubled, halved) in doubled_rest.chunks_exact(2).zip(chunks.into_remainder()) {
+        let x = u16::from_be_bytes([doubled[0], doubled[1]]) as u64;
+        *halved = halve_u64(x) as u8;
+    }
+}
+
+/// Halve every pair of bits into one bit, the first one of the pair.
+/// The length of `doubled_array` has to be even.
+pub fn halve_bits(doubled_array: &[u8]) -> Vec<u8> {
+    assert!(doubled_array.len().is_multiple_of(2));
+    let mut array = vec![0; doubled_array.len() / 2];
+    halve_bits_slice(doubled_array, &mut array);
+    array
+}
+
+/// Bit offset of the first pair of bits in `doubled_array` whose bits differ, which doubling
+/// never produces. `None` if every pair is valid.
+pub fn find_invalid_pair(doubled_array: &[u8]) -> Option<usize> {
+    // flags the second bit of every pair that differs, the pair starts one bit before it
+    let first_invalid = |x: u64| match (x ^ (x >> 1)) & 0x5555_5555_5555_5555 {
+        0 => None,
+        invalid => Some(invalid.leading_zeros() as usize - 1),
+    };
+
+    let chunks = doubled_array.chunks_exact(8);
+    let rest = chunks.remainder();
+    for (i, chunk) in chunks.enumerate() {
+        if let Some(bit) = first_invalid(u64::from_be_bytes(chunk.try_into().unwrap())) {
+            return Some(i * 64 + bit);
+        }
+    }
+    // zeros are valid pairs
+    let mut last = [0; 8];
+    last[..rest.len()].copy_from_slice(rest);
+    first_invalid(u64::from_be_bytes(last)).map(|bit| (doubled_array.len() - rest.len()) * 8 + bit)
+}
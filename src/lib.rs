@@ -1,26 +1,53 @@
 // #![feature(stdsimd)]
-#![feature(sync_unsafe_cell)]
 #![feature(portable_simd)]
 
+mod aligned;
+pub use aligned::*;
+mod bitmap;
+pub use bitmap::*;
+mod bitplanes;
+pub use bitplanes::*;
+mod depth;
+pub use depth::*;
+pub mod dsd;
+mod file;
+pub use file::*;
+pub mod font;
+pub mod formats;
+mod halve;
+pub use halve::*;
 mod lookups;
 pub use lookups::*;
 mod multipliers;
 pub use multipliers::*;
 mod opt;
 pub use opt::*;
+mod parallel;
+pub use parallel::*;
+mod pipeline;
+pub use pipeline::*;
+mod resample;
+pub use resample::*;
+mod reverse;
+pub use reverse::*;
+mod store;
+pub use store::*;
+mod stream;
+pub use stream::*;
+mod threads;
+pub use threads::*;
+mod transpose;
+pub use transpose::*;
+mod unpack;
+pub use unpack::*;
 
 use rand::{Rng, SeedableRng};
+use std::simd::Simd;
 
-#[repr(C, align(64))]
-struct Array{
-    pub data: Vec<u8>,
-}
-
+/// Random bytes from a fixed seed. The `Vec` is not aligned, see `generate_aligned_array`.
+#[allow(clippy::needless_range_loop)]
 pub fn generate_array(size: usize) -> Vec<u8> {
-    // let mut array = vec![0; size];
-    let mut array = Array {
-        data: vec![0; size],
-    }.data;
+    let mut array = vec![0; size];
     // let mut random_start = 25487;
 
     // initialize rand with a seed
@@ -35,7 +62,7 @@ pub fn generate_array(size: usize) -> Vec<u8> {
     array
 }
 
-pub fn double_array_sisd_laura_orig(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_sisd_laura_orig(array: &[u8]) -> Vec<u8> {
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
 
@@ -61,7 +88,7 @@ pub fn double_array_sisd_laura_orig(array: &Vec<u8>) -> Vec<u8> {
     doubled_array
 }
 
-pub fn double_array_sisd_laura(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_sisd_laura(array: &[u8]) -> Vec<u8> {
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
 
@@ -78,7 +105,7 @@ pub fn double_array_sisd_laura(array: &Vec<u8>) -> Vec<u8> {
         a += ((array[i] & n2) as u16).pow(2) & m2;
         a += ((array[i] & n3) as u16).pow(2) & m3;
 
-        a = a * 3;
+        a *= 3;
 
         doubled_array[i * 2] += (a >> 8) as u8;
         doubled_array[i * 2 + 1] += a as u8;
@@ -86,7 +113,7 @@ pub fn double_array_sisd_laura(array: &Vec<u8>) -> Vec<u8> {
     doubled_array
 }
 
-pub fn double_array_sisd_laura_u32(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_sisd_laura_u32(array: &[u8]) -> Vec<u8> {
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
 
@@ -122,7 +149,7 @@ pub fn double_array_sisd_laura_u32(array: &Vec<u8>) -> Vec<u8> {
 }
 
 #[cfg(all(
-    any(target_arch = "x86_64"),
+    target_arch = "x86_64",
     target_feature = "avx512f",
     target_feature = "avx512vl",
     target_feature = "avx512bw",
@@ -130,7 +157,8 @@ pub fn double_array_sisd_laura_u32(array: &Vec<u8>) -> Vec<u8> {
     target_feature = "avx512vbmi2",
     target_feature = "avx512bitalg"
 ))]
-pub fn double_array_simd_laura(array: &Vec<u8>) -> Vec<u8> {
+#[allow(clippy::needless_range_loop)]
+pub fn double_array_simd_laura(array: &[u8]) -> Vec<u8> {
     use std::arch::x86_64::*;
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
@@ -212,7 +240,129 @@ pub fn double_array_simd_laura(array: &Vec<u8>) -> Vec<u8> {
     doubled_array
 }
 
-// pub fn double_array_sisd_laura(array: &Vec<u8>) -> Vec<u8> {
+/// The carry-less square the laura kernels imitate, done directly with PCLMULQDQ.
+/// Squaring over GF(2) moves bit `i` to bit `2 * i`, OR-ing in a copy shifted by one doubles it.
+/// Falls back to `double_array_lookup_u4` if the CPU does not have PCLMULQDQ.
+#[cfg(target_arch = "x86_64")]
+pub fn double_array_clmul(array: &[u8]) -> Vec<u8> {
+    if is_x86_feature_detected!("pclmulqdq") && is_x86_feature_detected!("ssse3") {
+        unsafe { double_array_clmul_inner(array) }
+    } else {
+        double_array_lookup_u4(array)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "pclmulqdq,ssse3")]
+unsafe fn double_array_clmul_inner(array: &[u8]) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    let mut doubled_array: Vec<u8> = Vec::with_capacity(array.len() * 2);
+    let chunks = array.chunks_exact(16);
+    let rest = chunks.remainder();
+
+    // byte swap each 64-bit lane on the way in, so the first byte is the most significant,
+    // and the whole 128-bit square on the way out
+    let swap_64 = _mm_setr_epi8(7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8);
+    let swap_128 = _mm_setr_epi8(15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0);
+
+    let mut_ptr = doubled_array.as_mut_ptr();
+    for (i, chunk) in chunks.enumerate() {
+        let input = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+        let input = _mm_shuffle_epi8(input, swap_64);
+        let square_lo = _mm_clmulepi64_si128(input, input, 0x00);
+        let square_hi = _mm_clmulepi64_si128(input, input, 0x11);
+        // only even bits are set, so the 64-bit shift never has to carry into the next lane
+        let doubled_lo = _mm_or_si128(square_lo, _mm_slli_epi64(square_lo, 1));
+        let doubled_hi = _mm_or_si128(square_hi, _mm_slli_epi64(square_hi, 1));
+        _mm_storeu_si128(
+            mut_ptr.add(i * 32) as *mut __m128i,
+            _mm_shuffle_epi8(doubled_lo, swap_128),
+        );
+        _mm_storeu_si128(
+            mut_ptr.add(i * 32 + 16) as *mut __m128i,
+            _mm_shuffle_epi8(doubled_hi, swap_128),
+        );
+    }
+    doubled_array.set_len(array.len() / 16 * 32);
+
+    // deal with the rest of the array
+    doubled_array.extend(double_array_lookup_u4(rest));
+    doubled_array
+}
+
+/// Whether PDEP is fast on this CPU. AMD implemented it in microcode before Zen 3 (family 19h),
+/// where it takes hundreds of cycles instead of one, and so does Hygon, whose Dhyana is a Zen 1.
+#[cfg(target_arch = "x86_64")]
+fn has_fast_pdep() -> bool {
+    use std::arch::x86_64::__cpuid;
+
+    lazy_static::lazy_static! {
+        static ref FAST_PDEP: bool = {
+            if !is_x86_feature_detected!("bmi2") {
+                false
+            } else {
+                let vendor = __cpuid(0);
+                let vendor = [vendor.ebx, vendor.edx, vendor.ecx];
+                // "AuthenticAMD" or "HygonGenuine"
+                let is_amd = vendor == [0x6874_7541, 0x6974_6e65, 0x444d_4163]
+                    || vendor == [0x6f67_7948, 0x6e65_476e, 0x656e_6975];
+                let signature = __cpuid(1).eax;
+                let family = match (signature >> 8) & 0xf {
+                    0xf => 0xf + ((signature >> 20) & 0xff),
+                    family => family,
+                };
+                !is_amd || family >= 0x19
+            }
+        };
+    }
+    *FAST_PDEP
+}
+
+/// PDEP into every other bit spreads 32 bits at once, OR-ing in a copy shifted by one doubles them.
+/// Falls back to `double_array_lookup_u4` on CPUs without BMI2 or with a slow PDEP.
+#[cfg(target_arch = "x86_64")]
+pub fn double_array_pdep(array: &[u8]) -> Vec<u8> {
+    if has_fast_pdep() {
+        unsafe { double_array_pdep_inner(array) }
+    } else {
+        double_array_lookup_u4(array)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn double_array_pdep_inner(array: &[u8]) -> Vec<u8> {
+    use std::arch::x86_64::_pdep_u64;
+
+    const EVEN_BITS: u64 = 0x5555_5555_5555_5555;
+
+    let mut doubled_array = vec![0; array.len() * 2];
+    let chunks = array.chunks_exact(8);
+    let rest = chunks.remainder();
+    for (chunk, doubled) in chunks.zip(doubled_array.chunks_exact_mut(16)) {
+        let value = u64::from_be_bytes(chunk.try_into().unwrap());
+        let hi = _pdep_u64(value >> 32, EVEN_BITS);
+        let lo = _pdep_u64(value & 0xffff_ffff, EVEN_BITS);
+        doubled[..8].copy_from_slice(&(hi | (hi << 1)).to_be_bytes());
+        doubled[8..].copy_from_slice(&(lo | (lo << 1)).to_be_bytes());
+    }
+
+    // deal with the rest of the array, four bytes at a time while they last
+    let (words, bytes) = rest.split_at(rest.len() - rest.len() % 4);
+    let start = array.len() - rest.len();
+    if !words.is_empty() {
+        let value = u32::from_be_bytes(words.try_into().unwrap()) as u64;
+        let doubled = _pdep_u64(value, EVEN_BITS);
+        doubled_array[start * 2..start * 2 + 8]
+            .copy_from_slice(&(doubled | (doubled << 1)).to_be_bytes());
+    }
+    let start = start + words.len();
+    doubled_array[start * 2..].copy_from_slice(&double_array_lookup_u4(bytes));
+    doubled_array
+}
+
+// pub fn double_array_sisd_laura(array: &[u8]) -> Vec<u8> {
 //     let size = array.len();
 //     let mut doubled_array = vec![0; size * 2];
 
@@ -235,7 +385,7 @@ pub fn double_array_simd_laura(array: &Vec<u8>) -> Vec<u8> {
 /// double up each bit in the array
 /// [1|2|3|4|5|6|7|8, 9|10|11|12|13|14|15|16] ->
 /// [1|1|2|2|3|3|4|4, 5|5|6|6|7|7|8|8, 9|9|10|10|11|11|12|12, 13|13|14|14|15|15|16|16]
-pub fn double_array_sisd(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_sisd(array: &[u8]) -> Vec<u8> {
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
 
@@ -254,7 +404,7 @@ pub fn double_array_sisd(array: &Vec<u8>) -> Vec<u8> {
     any(target_arch = "aarch64", target_arch = "arm"),
     target_feature = "neon"
 ))]
-pub fn double_array_simd(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_simd(array: &[u8]) -> Vec<u8> {
     use std::arch::aarch64::*;
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
@@ -307,40 +457,72 @@ const LOOKUP: LookupTable = LookupTable {
     ],
 };
 
-#[cfg(all(
-    any(target_arch = "x86_64"),
-    target_feature = "avx512f",
-    target_feature = "avx512vl",
-    target_feature = "avx512bw",
-    target_feature = "avx512vbmi",
-    target_feature = "avx512vbmi2",
-    target_feature = "avx512bitalg"
-))]
-pub fn double_array_lookup_avx_u4(array: &[u8]) -> Vec<u8> {
-    use std::arch::x86_64::*;
+/// Write the `array.len() * 2` doubled bytes to `doubled_array` one byte at a time, for the
+/// prologues and tails of the x86 LUT kernels.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+unsafe fn lookup_u4_raw(array: &[u8], doubled_array: *mut u8) {
+    for (i, &x) in array.iter().enumerate() {
+        *doubled_array.add(i * 2) = LOOKUP.table[(x >> 4) as usize];
+        *doubled_array.add(i * 2 + 1) = LOOKUP.table[(x & 0b1111) as usize];
+    }
+}
 
-    let mut doubled_array: Vec<u8> = Vec::with_capacity(array.len() * 2);
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub fn double_array_lookup_ssse3_u4(array: &[u8]) -> Vec<u8> {
+    double_array_lookup_ssse3_u4_with(array, StoreMode::Regular)
+}
 
-    let (pre, array, rest) = unsafe { array.align_to::<__m128i>() };
+/// The old name of `double_array_lookup_ssse3_u4`, the kernel only ever needed SSSE3.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+#[deprecated(note = "renamed to `double_array_lookup_ssse3_u4`")]
+pub fn double_array_lookup_avx_u4(array: &[u8]) -> Vec<u8> {
+    double_array_lookup_ssse3_u4(array)
+}
 
-    for i in pre {
-        doubled_array.push(LOOKUP.table[(i >> 4) as usize]);
-        doubled_array.push(LOOKUP.table[(i & 0b1111) as usize]);
+/// `double_array_lookup_ssse3_u4` with a choice of regular or streaming stores.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub fn double_array_lookup_ssse3_u4_with(array: &[u8], store: StoreMode) -> Vec<u8> {
+    let mut doubled_array = Vec::with_capacity(array.len() * 2);
+    unsafe {
+        double_array_lookup_ssse3_u4_raw(array, doubled_array.as_mut_ptr(), store);
+        doubled_array.set_len(array.len() * 2);
     }
+    doubled_array
+}
+
+/// `double_array_lookup_ssse3_u4_with` into `doubled_array`, which is twice as long as `array`.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub fn double_array_lookup_ssse3_u4_slice_with(
+    array: &[u8],
+    doubled_array: &mut [u8],
+    store: StoreMode,
+) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+    unsafe { double_array_lookup_ssse3_u4_raw(array, doubled_array.as_mut_ptr(), store) }
+}
+
+/// Write the `array.len() * 2` doubled bytes to `doubled_array`.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+unsafe fn double_array_lookup_ssse3_u4_raw(array: &[u8], doubled_array: *mut u8, store: StoreMode) {
+    use std::arch::x86_64::*;
+
+    let (pre_len, streaming) =
+        prologue_len(array, doubled_array, 16, store.streams(array.len() * 2));
+    let (pre, array) = array.split_at(pre_len);
+    let chunks = array.chunks_exact(16);
+    let rest = chunks.remainder();
+
+    lookup_u4_raw(pre, doubled_array);
 
     unsafe {
         // store LUT in a vector
         let lookup = _mm_load_si128(LOOKUP.table.as_ptr() as *const __m128i);
+        // mask for low nibble
+        let mask = _mm_set1_epi8(0x0f_i8);
 
-        let mut_ptr = doubled_array.as_mut_ptr();
-        let pre_len_x2 = pre.len() * 2;
-        for i in 0..array.len() {
-            // let input = vld1q_u8(array.as_ptr().add(i));
-            let input = *array.get_unchecked(i);
-            // get low half of each byte by masking out the high half
-
-            // mask for low nibble
-            let mask = _mm_set1_epi8(0x0f as i8);
+        let mut_ptr = doubled_array.add(pre.len() * 2);
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
             // isolate low nibble
             let input_lo = _mm_and_si128(input, mask);
             // get high nibble by shifting right 4 bits (use 16-bit shift to avoid cross-byte shifts), then mask
@@ -352,24 +534,105 @@ pub fn double_array_lookup_avx_u4(array: &[u8]) -> Vec<u8> {
             let out0 = _mm_unpacklo_epi8(output_hi, output_lo);
             let out1 = _mm_unpackhi_epi8(output_hi, output_lo);
             // store the two 16-byte vectors (total 32 bytes)
-            _mm_storeu_si128(mut_ptr.add(i * 32 + pre_len_x2) as *mut __m128i, out0);
-            _mm_storeu_si128(mut_ptr.add(i * 32 + pre_len_x2 + 16) as *mut __m128i, out1);
+            let out_ptr = mut_ptr.add(i * 32) as *mut __m128i;
+            if streaming {
+                _mm_stream_si128(out_ptr, out0);
+                _mm_stream_si128(out_ptr.add(1), out1);
+            } else {
+                _mm_storeu_si128(out_ptr, out0);
+                _mm_storeu_si128(out_ptr.add(1), out1);
+            }
+        }
+        if streaming {
+            _mm_sfence();
         }
-        _mm_sfence();
-        doubled_array.set_len(array.len() * 32 + pre.len() * 2);
-    }
 
-    // deal with the rest of the array
-    for i in rest {
-        doubled_array.push(LOOKUP.table[(i >> 4) as usize]);
-        doubled_array.push(LOOKUP.table[(i & 0b1111) as usize]);
+        // deal with the rest of the array
+        lookup_u4_raw(rest, mut_ptr.add((array.len() - rest.len()) * 2));
     }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub fn double_array_lookup_avx2_u4(array: &[u8]) -> Vec<u8> {
+    double_array_lookup_avx2_u4_with(array, StoreMode::Regular)
+}
 
+/// `double_array_lookup_avx2_u4` with a choice of regular or streaming stores.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub fn double_array_lookup_avx2_u4_with(array: &[u8], store: StoreMode) -> Vec<u8> {
+    let mut doubled_array = Vec::with_capacity(array.len() * 2);
+    unsafe {
+        double_array_lookup_avx2_u4_raw(array, doubled_array.as_mut_ptr(), store);
+        doubled_array.set_len(array.len() * 2);
+    }
     doubled_array
 }
 
+/// `double_array_lookup_avx2_u4_with` into `doubled_array`, which is twice as long as `array`.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub fn double_array_lookup_avx2_u4_slice_with(
+    array: &[u8],
+    doubled_array: &mut [u8],
+    store: StoreMode,
+) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+    unsafe { double_array_lookup_avx2_u4_raw(array, doubled_array.as_mut_ptr(), store) }
+}
+
+/// Write the `array.len() * 2` doubled bytes to `doubled_array`.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+unsafe fn double_array_lookup_avx2_u4_raw(array: &[u8], doubled_array: *mut u8, store: StoreMode) {
+    use std::arch::x86_64::*;
+
+    let (pre_len, streaming) =
+        prologue_len(array, doubled_array, 32, store.streams(array.len() * 2));
+    let (pre, array) = array.split_at(pre_len);
+    let chunks = array.chunks_exact(32);
+    let rest = chunks.remainder();
+
+    lookup_u4_raw(pre, doubled_array);
+
+    unsafe {
+        // the shuffle works within 128-bit lanes, so both lanes get the LUT
+        let lookup = _mm256_broadcastsi128_si256(_mm_load_si128(
+            LOOKUP.table.as_ptr() as *const __m128i
+        ));
+        let mask = _mm256_set1_epi8(0x0f_i8);
+
+        let mut_ptr = doubled_array.add(pre.len() * 2);
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            let input_lo = _mm256_and_si256(input, mask);
+            let input_hi = _mm256_and_si256(_mm256_srli_epi16(input, 4), mask);
+            let output_lo = _mm256_shuffle_epi8(lookup, input_lo);
+            let output_hi = _mm256_shuffle_epi8(lookup, input_hi);
+            // unpack interleaves within each lane, so out0 holds bytes 0..8 and 16..24,
+            // out1 holds bytes 8..16 and 24..32
+            let out0 = _mm256_unpacklo_epi8(output_hi, output_lo);
+            let out1 = _mm256_unpackhi_epi8(output_hi, output_lo);
+            // put the lanes back in order
+            let first = _mm256_permute2x128_si256(out0, out1, 0x20);
+            let second = _mm256_permute2x128_si256(out0, out1, 0x31);
+            let out_ptr = mut_ptr.add(i * 64) as *mut __m256i;
+            if streaming {
+                _mm256_stream_si256(out_ptr, first);
+                _mm256_stream_si256(out_ptr.add(1), second);
+            } else {
+                _mm256_storeu_si256(out_ptr, first);
+                _mm256_storeu_si256(out_ptr.add(1), second);
+            }
+        }
+        if streaming {
+            _mm_sfence();
+        }
+
+        // deal with the rest of the array
+        lookup_u4_raw(rest, mut_ptr.add((array.len() - rest.len()) * 2));
+    }
+}
+
 #[cfg(all(
-    any(target_arch = "x86_64"),
+    target_arch = "x86_64",
     target_feature = "avx512f",
     target_feature = "avx512vl",
     target_feature = "avx512bw",
@@ -378,40 +641,85 @@ pub fn double_array_lookup_avx_u4(array: &[u8]) -> Vec<u8> {
     target_feature = "avx512bitalg"
 ))]
 pub fn double_array_lookup_avx512_u4(array: &[u8]) -> Vec<u8> {
-    use std::arch::x86_64::*;
+    double_array_lookup_avx512_u4_with(array, StoreMode::Regular)
+}
 
-    let mut doubled_array: Vec<u8> = Vec::with_capacity(array.len() * 2);
+/// `double_array_lookup_avx512_u4` with a choice of regular or streaming stores.
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "avx512f",
+    target_feature = "avx512vl",
+    target_feature = "avx512bw",
+    target_feature = "avx512vbmi",
+    target_feature = "avx512vbmi2",
+    target_feature = "avx512bitalg"
+))]
+pub fn double_array_lookup_avx512_u4_with(array: &[u8], store: StoreMode) -> Vec<u8> {
+    let mut doubled_array = Vec::with_capacity(array.len() * 2);
+    unsafe {
+        double_array_lookup_avx512_u4_raw(array, doubled_array.as_mut_ptr(), store);
+        doubled_array.set_len(array.len() * 2);
+    }
+    doubled_array
+}
+
+/// `double_array_lookup_avx512_u4_with` into `doubled_array`, which is twice as long as `array`.
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "avx512f",
+    target_feature = "avx512vl",
+    target_feature = "avx512bw",
+    target_feature = "avx512vbmi",
+    target_feature = "avx512vbmi2",
+    target_feature = "avx512bitalg"
+))]
+pub fn double_array_lookup_avx512_u4_slice_with(
+    array: &[u8],
+    doubled_array: &mut [u8],
+    store: StoreMode,
+) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+    unsafe { double_array_lookup_avx512_u4_raw(array, doubled_array.as_mut_ptr(), store) }
+}
 
-    let (pre, array, rest) = unsafe { array.align_to::<__m512i>() };
+/// Write the `array.len() * 2` doubled bytes to `doubled_array`.
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "avx512f",
+    target_feature = "avx512vl",
+    target_feature = "avx512bw",
+    target_feature = "avx512vbmi",
+    target_feature = "avx512vbmi2",
+    target_feature = "avx512bitalg"
+))]
+unsafe fn double_array_lookup_avx512_u4_raw(
+    array: &[u8],
+    doubled_array: *mut u8,
+    store: StoreMode,
+) {
+    use std::arch::x86_64::*;
 
-    // println!(
-    //     "pre len: {}, array len: {}, rest len: {}",
-    //     pre.len(),
-    //     array.len(),
-    //     rest.len()
-    // );
+    let (pre_len, streaming) =
+        prologue_len(array, doubled_array, 64, store.streams(array.len() * 2));
+    let (pre, array) = array.split_at(pre_len);
+    let chunks = array.chunks_exact(64);
+    let rest = chunks.remainder();
 
-    for i in pre {
-        doubled_array.push(LOOKUP.table[(i >> 4) as usize]);
-        doubled_array.push(LOOKUP.table[(i & 0b1111) as usize]);
-    }
+    lookup_u4_raw(pre, doubled_array);
 
-    
     unsafe {
         // store LUT in a vector
         let lookup = _mm_load_si128(LOOKUP.table.as_ptr() as *const __m128i);
         let lookup = _mm512_broadcast_i32x4(lookup);
-        // let lookup = _mm512_add_epi(lookup);
         // mask for low nibble
-        let mask = _mm512_set1_epi8(0x0f as i8);
-
-        let mut_ptr = doubled_array.as_mut_ptr();
-        let pre_len_x2 = pre.len() * 2;
-        for i in 0..array.len() {
-            // let input = vld1q_u8(array.as_ptr().add(i));
-            let input = *array.get_unchecked(i);
-            // get low half of each byte by masking out the high half
-
+        let mask = _mm512_set1_epi8(0x0f_i8);
+        // 64 bit lane indices into (out0, out1) for the first and second half of the output
+        let idx_lo = _mm512_setr_epi64(0, 1, 8, 9, 2, 3, 10, 11);
+        let idx_hi = _mm512_setr_epi64(4, 5, 12, 13, 6, 7, 14, 15);
+
+        let mut_ptr = doubled_array.add(pre.len() * 2);
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm512_loadu_si512(chunk.as_ptr() as *const __m512i);
             // isolate low nibble
             let input_lo = _mm512_and_si512(input, mask);
             // get high nibble by shifting right 4 bits (use 16-bit shift to avoid cross-byte shifts), then mask
@@ -423,48 +731,33 @@ pub fn double_array_lookup_avx512_u4(array: &[u8]) -> Vec<u8> {
             let out0 = _mm512_unpacklo_epi8(output_hi, output_lo);
             let out1 = _mm512_unpackhi_epi8(output_hi, output_lo);
 
-            // // swap 128 bit lanes within each 512-bit vector
-            // let idx = _mm512_setr_epi64(0, 1, 4, 5, 2, 3, 6, 7);
-            // let out0 = _mm512_permutexvar_epi64(
-            //     idx,
-            //     out0,
-            // );
-            // let out1 = _mm512_permutexvar_epi64(
-            //     idx,
-            //     out1,
-            // );
-
-            // store location: location in 128 bit lanes of out0_out1
-            // 0: 0
-            // 1: 4
-            // 2: 1
-            // 3: 5
-            // 4: 2
-            // 5: 6
-            // 6: 3
-            // 7: 7
+            // unpack works within 128 bit lanes, so gather the lanes back into input order
+            let (out0, out1) = (
+                _mm512_permutex2var_epi64(out0, idx_lo, out1),
+                _mm512_permutex2var_epi64(out0, idx_hi, out1),
+            );
 
             // store the two 64-byte vectors (total 128 bytes)
-            // _mm512_stream_si512(mut_ptr.add(pre_len_x2 + i * 128) as *mut __m512i, out0);
-            // _mm512_stream_si512(mut_ptr.add(pre_len_x2 + i * 128 + 64) as *mut __m512i, out1);
-            _mm512_storeu_si512(mut_ptr.add(i * 128 + pre_len_x2) as *mut __m512i, out0);
-            _mm512_storeu_si512(mut_ptr.add(i * 128 + pre_len_x2 + 64) as *mut __m512i, out1);
+            let out_ptr = mut_ptr.add(i * 128) as *mut __m512i;
+            if streaming {
+                _mm512_stream_si512(out_ptr, out0);
+                _mm512_stream_si512(out_ptr.add(1), out1);
+            } else {
+                _mm512_storeu_si512(out_ptr, out0);
+                _mm512_storeu_si512(out_ptr.add(1), out1);
+            }
+        }
+        if streaming {
+            _mm_sfence();
         }
-        _mm_sfence();
-        doubled_array.set_len(array.len() * 128 + pre.len() * 2);
-    }
 
-    // deal with the rest of the array
-    for i in rest {
-        doubled_array.push(LOOKUP.table[(i >> 4) as usize]);
-        doubled_array.push(LOOKUP.table[(i & 0b1111) as usize]);
+        // deal with the rest of the array
+        lookup_u4_raw(rest, mut_ptr.add((array.len() - rest.len()) * 2));
     }
-
-    doubled_array
 }
 
 #[cfg(all(
-    any(target_arch = "x86_64"),
+    target_arch = "x86_64",
     target_feature = "avx512f",
     target_feature = "avx512vl",
     target_feature = "avx512bw",
@@ -505,6 +798,152 @@ pub fn throughput_test(array: &[u8]) -> Vec<u8> {
     doubled_array
 }
 
+/// The nibble LUT kernel with `std::simd`, `N` bytes at a time, for any target.
+/// `N` is the vector width in bytes, 16, 32 or 64.
+pub fn double_array_portable_simd<const N: usize>(array: &[u8]) -> Vec<u8> {
+    let mut doubled_array = vec![0; array.len() * 2];
+    double_array_portable_simd_slice::<N>(array, &mut doubled_array);
+    doubled_array
+}
+
+/// Same as `double_array_portable_simd`, but writes into `doubled_array`.
+pub fn double_array_portable_simd_slice<const N: usize>(array: &[u8], doubled_array: &mut [u8]) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+
+    let chunks = array.chunks_exact(N);
+    let rest = chunks.remainder();
+
+    // the LUT fills the first 16 lanes, nibbles never index past them
+    let lookup = Simd::<u8, N>::load_or_default(&LOOKUP.table);
+    let mask = Simd::<u8, N>::splat(0b1111);
+
+    let (doubled_chunks, doubled_rest) = doubled_array.split_at_mut(array.len() / N * 2 * N);
+    for (chunk, doubled) in chunks.zip(doubled_chunks.chunks_exact_mut(2 * N)) {
+        let input = Simd::<u8, N>::from_slice(chunk);
+        let output_hi = lookup.swizzle_dyn(input >> 4);
+        let output_lo = lookup.swizzle_dyn(input & mask);
+        // [hi0, lo0, hi1, lo1, ...]
+        let (out0, out1) = output_hi.interleave(output_lo);
+        out0.copy_to_slice(&mut doubled[..N]);
+        out1.copy_to_slice(&mut doubled[N..]);
+    }
+
+    // deal with the rest of the array
+    for (i, doubled) in rest.iter().zip(doubled_rest.chunks_exact_mut(2)) {
+        doubled[0] = LOOKUP.table[(i >> 4) as usize];
+        doubled[1] = LOOKUP.table[(i & 0b1111) as usize];
+    }
+}
+
+/// Double up each bit in the array with the fastest implementation available, on all
+/// threads of the global rayon pool from `parallel_threshold()` bytes on.
+pub fn double_bits(array: &[u8]) -> Vec<u8> {
+    if array.len() >= parallel::MIN_PARALLEL_THRESHOLD && array.len() >= parallel_threshold() {
+        double_bits_parallel(array, None)
+    } else {
+        double_bits_sequential(array)
+    }
+}
+
+/// Double up each bit in the array with the fastest single threaded implementation available,
+/// with streaming stores if the output does not fit into the last level cache.
+pub fn double_bits_sequential(array: &[u8]) -> Vec<u8> {
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "avx512f",
+        target_feature = "avx512vl",
+        target_feature = "avx512bw",
+        target_feature = "avx512vbmi",
+        target_feature = "avx512vbmi2",
+        target_feature = "avx512bitalg"
+    ))]
+    return double_array_lookup_avx512_u4_with(array, StoreMode::Auto);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "avx2",
+        not(all(
+            target_feature = "avx512f",
+            target_feature = "avx512vl",
+            target_feature = "avx512bw",
+            target_feature = "avx512vbmi",
+            target_feature = "avx512vbmi2",
+            target_feature = "avx512bitalg"
+        ))
+    ))]
+    return double_array_lookup_avx2_u4_with(array, StoreMode::Auto);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "ssse3",
+        not(target_feature = "avx2")
+    ))]
+    return double_array_lookup_ssse3_u4_with(array, StoreMode::Auto);
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    return double_array_lookup_neon_u4(array);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    return double_array_portable_simd::<16>(array);
+    #[allow(unreachable_code)]
+    double_array_lookup_u4(array)
+}
+
+/// Double up each bit of `array` into `doubled_array`, which is twice as long,
+/// with the fastest slice kernel available.
+pub fn double_bits_slice(array: &[u8], doubled_array: &mut [u8]) {
+    double_bits_slice_with(array, doubled_array, StoreMode::Regular)
+}
+
+/// `double_bits_slice` with a choice of regular or streaming stores. Only the x86 kernels can
+/// stream, the others always store regularly.
+pub fn double_bits_slice_with(array: &[u8], doubled_array: &mut [u8], store: StoreMode) {
+    // only the x86 kernels look at it
+    let _ = store;
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "avx512f",
+        target_feature = "avx512vl",
+        target_feature = "avx512bw",
+        target_feature = "avx512vbmi",
+        target_feature = "avx512vbmi2",
+        target_feature = "avx512bitalg"
+    ))]
+    return double_array_lookup_avx512_u4_slice_with(array, doubled_array, store);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "avx2",
+        not(all(
+            target_feature = "avx512f",
+            target_feature = "avx512vl",
+            target_feature = "avx512bw",
+            target_feature = "avx512vbmi",
+            target_feature = "avx512vbmi2",
+            target_feature = "avx512bitalg"
+        ))
+    ))]
+    return double_array_lookup_avx2_u4_slice_with(array, doubled_array, store);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "ssse3",
+        not(target_feature = "avx2")
+    ))]
+    return double_array_lookup_ssse3_u4_slice_with(array, doubled_array, store);
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    return double_array_lookup_neon_u4_slice(array, doubled_array);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    return double_array_portable_simd_slice::<16>(array, doubled_array);
+    #[allow(unreachable_code)]
+    double_array_lookup_u4_slice(array, doubled_array)
+}
+
+/// Repeat each bit in the array `n` times with the fastest implementation available.
+pub fn multiply_bits(array: &[u8], n: usize) -> Vec<u8> {
+    match n {
+        1 => array.to_vec(),
+        2 => double_bits(array),
+        4 => double_bits(&double_bits(array)),
+        _ => multiply_array_lookup_u8(array, n),
+    }
+}
+
+#[allow(clippy::needless_range_loop)]
 pub fn print_array(array: &[u8]) {
     let x = array.len();
 
@@ -514,6 +953,7 @@ pub fn print_array(array: &[u8]) {
     println!();
 }
 
+#[allow(clippy::needless_range_loop)]
 pub fn print_array_spaced(array: &[u8]) {
     let x = array.len();
 
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 pub fn double_array_lookup_u4(array: &[u8]) -> Vec<u8> {
     let doubled_array: Vec<u8> = array
         .iter()
@@ -31,6 +34,36 @@ pub fn double_array_lookup_u4(array: &[u8]) -> Vec<u8> {
     doubled_array
 }
 
+/// Same as `double_array_lookup_u4`, but writes into `doubled_array`.
+pub fn double_array_lookup_u4_slice(array: &[u8], doubled_array: &mut [u8]) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+
+    #[rustfmt::skip]
+    const LOOKUP: [u8; 16] = [
+        0b00000000,
+        0b00000011,
+        0b00001100,
+        0b00001111,
+        0b00110000,
+        0b00110011,
+        0b00111100,
+        0b00111111,
+        0b11000000,
+        0b11000011,
+        0b11001100,
+        0b11001111,
+        0b11110000,
+        0b11110011,
+        0b11111100,
+        0b11111111,
+    ];
+
+    for (&x, doubled) in array.iter().zip(doubled_array.chunks_exact_mut(2)) {
+        doubled[0] = LOOKUP[(x >> 4) as usize];
+        doubled[1] = LOOKUP[(x & 0b1111) as usize];
+    }
+}
+
 pub fn double_array_lookup_u8(array: &[u8]) -> Vec<u8> {
     let lookup: [u16; 256] = (0..=255u8)
         .map(|x| {
@@ -52,6 +85,79 @@ pub fn double_array_lookup_u8(array: &[u8]) -> Vec<u8> {
         .collect()
 }
 
+/// Repeat each of the lowest `bits` bits of `x` `n` times, MSB-first, into `n * bits / 8` bytes.
+fn multiply_bits_of(x: u8, bits: usize, n: usize) -> Vec<u8> {
+    let mut res = vec![0u8; n * bits / 8];
+    for j in 0..bits {
+        let bit = (x >> (bits - 1 - j)) & 1;
+        for k in 0..n {
+            let pos = j * n + k;
+            res[pos / 8] |= bit << (7 - pos % 8);
+        }
+    }
+    res
+}
+
+/// Repeat each bit in the array `n` times, `n` has to be even.
+/// Same as `double_array_lookup_u4`, but each nibble maps to `n / 2` bytes.
+pub fn multiply_array_lookup_u4(array: &[u8], n: usize) -> Vec<u8> {
+    assert!(n > 0 && n.is_multiple_of(2), "n has to be even");
+    let nibble_bytes = n / 2;
+    let lookup: Vec<u8> = (0..16u8).flat_map(|x| multiply_bits_of(x, 4, n)).collect();
+
+    let mut multiplied_array = Vec::with_capacity(array.len() * n);
+    for &x in array {
+        let high_nibble = (x >> 4) as usize;
+        let low_nibble = (x & 0b0000_1111) as usize;
+        multiplied_array.extend_from_slice(
+            &lookup[high_nibble * nibble_bytes..(high_nibble + 1) * nibble_bytes],
+        );
+        multiplied_array
+            .extend_from_slice(&lookup[low_nibble * nibble_bytes..(low_nibble + 1) * nibble_bytes]);
+    }
+    multiplied_array
+}
+
+/// Largest factor whose `multiply_array_lookup_u8` table is kept, 16 KiB of table.
+const MAX_CACHED_FACTOR: usize = 64;
+
+lazy_static::lazy_static! {
+    /// `multiply_array_lookup_u8` tables by factor, built on first use.
+    static ref LOOKUPS_U8: Mutex<HashMap<usize, Arc<[u8]>>> = Mutex::default();
+}
+
+/// The `n` bytes every byte maps to, for all 256 bytes one after the other. Tables for
+/// factors up to `MAX_CACHED_FACTOR` are built once, bigger ones on every call.
+fn multiply_lookup_u8(n: usize) -> Arc<[u8]> {
+    let build = || {
+        (0..=255u8)
+            .flat_map(|x| multiply_bits_of(x, 8, n))
+            .collect()
+    };
+    if n > MAX_CACHED_FACTOR {
+        return build();
+    }
+    LOOKUPS_U8
+        .lock()
+        .unwrap()
+        .entry(n)
+        .or_insert_with(build)
+        .clone()
+}
+
+/// Repeat each bit in the array `n` times, each byte maps to `n` bytes.
+pub fn multiply_array_lookup_u8(array: &[u8], n: usize) -> Vec<u8> {
+    assert!(n > 0);
+    let lookup = multiply_lookup_u8(n);
+
+    let mut multiplied_array = Vec::with_capacity(array.len() * n);
+    for &x in array {
+        let x = x as usize;
+        multiplied_array.extend_from_slice(&lookup[x * n..(x + 1) * n]);
+    }
+    multiplied_array
+}
+
 pub fn double_array_lookup_u16(array: &[u8]) -> Vec<u8> {
     lazy_static::lazy_static! {
         static ref LOOKUP_U16: [u32; 65_536] = {
@@ -216,55 +322,6 @@ pub fn double_array_lookup_neon_u4_slice(array: &[u8], doubled_array: &mut [u8])
     }
 }
 
-/// Splits a slice into `n` equal chunks.
-/// The last chunk will be shorter if the length of the slice is not divisible by `n`.
-#[cfg(all(
-    any(target_arch = "aarch64", target_arch = "arm"),
-    target_feature = "neon"
-))]
-fn split_into_chunks<T>(slice: &[T], n: usize) -> Vec<&[T]> {
-    let mut chunks = Vec::with_capacity(n);
-    // rounded up
-    let chunk_size = (slice.len() + n - 1) / n;
-
-    for i in 0..n {
-        let start = i * chunk_size;
-        let end = usize::min(start + chunk_size, slice.len());
-        chunks.push(&slice[start..end]);
-    }
-
-    chunks
-}
-
-/// Same as `split_into_chunks`, but returns mutable slices.
-/// This is safe because the returned slices are disjoint.
-#[cfg(all(
-    any(target_arch = "aarch64", target_arch = "arm"),
-    target_feature = "neon"
-))]
-fn split_into_chunks_mut<T>(slice: &mut [T], n: usize) -> Vec<&mut [T]> {
-    let mut chunks = Vec::with_capacity(n);
-    // rounded up, pretend we're dealing with half the size
-    let chunk_size = (slice.len() / 2 + n - 1) / n * 2;
-
-    let len = slice.len();
-    // println!("len: {}", len);
-    for i in 0..n {
-        let start = i * chunk_size;
-        // println!("start: {}", start);
-        let end = usize::min(start + chunk_size, len);
-        // println!("end: {}", end);
-        unsafe {
-            chunks.push(std::slice::from_raw_parts_mut(
-                slice.as_mut_ptr().add(start),
-                end - start,
-            ));
-        }
-    }
-
-    chunks
-}
-
 #[cfg(all(
     any(target_arch = "aarch64", target_arch = "arm"),
     target_feature = "neon"
@@ -273,26 +330,12 @@ pub fn double_array_lookup_neon_u4_multithread(
     array: &[u8],
     thread_pool: &rayon::ThreadPool,
 ) -> Vec<u8> {
-    let mut doubled_array: Vec<u8> = Vec::with_capacity(array.len() * 2);
-    thread_pool.scope(|s| {
-        unsafe {
-            doubled_array.set_len(array.len() * 2);
-        }
-        let doubled_chunks = split_into_chunks_mut(&mut doubled_array, 8);
-        let array_chunks = split_into_chunks(array, 8);
-
-        for (doubled_chunk, array_chunk) in doubled_chunks.into_iter().zip(array_chunks) {
-            // println!("lengths: {}, {}", doubled_chunk.len(), array_chunk.len());
-            s.spawn(|_| {
-                double_array_lookup_neon_u4_slice(array_chunk, doubled_chunk);
-            });
-        }
-    });
-    unsafe {
-        doubled_array.set_len(array.len() * 2);
-    }
-
-    doubled_array
+    crate::double_bits_parallel_with(
+        array,
+        16,
+        double_array_lookup_neon_u4_slice,
+        Some(thread_pool),
+    )
 }
 
 #[cfg(all(
@@ -1,33 +1,14 @@
-// #[cfg(
-//     all(
-//         any(target_arch = "aarch64", target_arch = "arm"),
-//         target_feature = "neon"
-//     )
-// )]
-// pub fn rotate_array_neon(a: &[u8], b: &mut [u8]) {
-//     use std::arch::aarch64::*;
-
-//     let a = a.as_ptr();
-//     let b = b.as_mut_ptr();
-
-//     unsafe {
-//         let a = vld1q_u8(a);
-//         let c = vrev64q_u8(a);
-//         vst1q_u8(b, c);
-//     }
-// }
-
-// transpose an x by y 2d array
-// x is the number of rows
-// y is the number of columns
-
 use neon_test::*;
 
+/// `neon_test <input.pbm|input.xbm> <output.pbm|output.xbm> [scale]` scales an image,
+/// without arguments all implementations are checked against each other.
 fn main() {
-    // thread_priority::set_current_thread_priority(thread_priority::ThreadPriority::Crossplatform(
-    //     3.try_into().unwrap(),
-    // ))
-    // .unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 {
+        scale_image(&args[1..]);
+        return;
+    }
+
     let x = 128;
     let array = generate_array(x);
     // println!("Original array: ");
@@ -40,32 +21,37 @@ fn main() {
     let rotated_array_lookup_u4 = double_array_lookup_u4(&array);
     let rotated_array_lookup_u8 = double_array_lookup_u8(&array);
     let rotated_array_lookup_u16 = double_array_lookup_u16(&array);
+    #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+    {
+        let rotated_array_lut_simd_ssse3 = double_array_lookup_ssse3_u4(&array);
+        assert_eq!(rotated_array_sisd, rotated_array_lut_simd_ssse3);
+    }
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    {
+        let rotated_array_lut_simd_avx2 = double_array_lookup_avx2_u4(&array);
+        assert_eq!(rotated_array_sisd, rotated_array_lut_simd_avx2);
+    }
     #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "avx2"
+        target_arch = "x86_64",
+        target_feature = "avx512f",
+        target_feature = "avx512vl",
+        target_feature = "avx512bw",
+        target_feature = "avx512vbmi",
+        target_feature = "avx512vbmi2",
+        target_feature = "avx512bitalg"
     ))]
     {
-        let rotated_array_lut_simd_avx = double_array_lookup_avx_u4(&array);
-        assert_eq!(rotated_array_sisd, rotated_array_lut_simd_avx);
         let rotated_double_array_simd_laura = double_array_simd_laura(&array);
         assert_eq!(rotated_array_sisd, rotated_double_array_simd_laura);
         let rotated_double_array_simd_lut_simd_avx512 = double_array_lookup_avx512_u4(&array);
 
-        let orig = unsafe { rotated_array_sisd.align_to::<u128>().1 };
-        let avx = unsafe {
-            rotated_double_array_simd_lut_simd_avx512
-                .align_to::<u128>()
-                .1
-        };
-        assert_eq!(orig, avx);
-
         assert_eq!(
             rotated_array_sisd,
             rotated_double_array_simd_lut_simd_avx512
         );
     }
 
-    // let rotated_array_sisd_iter_rayon = double_array_sisd_opt_rayon(&array);
+    let rotated_array_sisd_iter_rayon = double_array_sisd_opt_rayon(&array);
     #[cfg(all(
         any(target_arch = "aarch64", target_arch = "arm"),
         target_feature = "neon"
@@ -93,6 +79,7 @@ fn main() {
     assert_eq!(rotated_array_sisd, rotated_array_laura_u32);
     assert_eq!(rotated_array_sisd, rotated_array_sisd_opt);
     assert_eq!(rotated_array_sisd, rotated_array_sisd_iter);
+    assert_eq!(rotated_array_sisd, rotated_array_sisd_iter_rayon);
     assert_eq!(rotated_array_sisd, rotated_array_lookup_u4);
     assert_eq!(rotated_array_sisd, rotated_array_lookup_u8);
     assert_eq!(rotated_array_sisd, rotated_array_lookup_u16);
@@ -102,10 +89,1133 @@ fn main() {
         target_feature = "neon"
     ))]
     assert_eq!(rotated_array_sisd, rotated_array_lut_simd);
-    #[cfg(all(
-        any(target_arch = "aarch64", target_arch = "arm"),
-        target_feature = "neon"
-    ))]
     assert_eq!(rotated_array_sisd, rotated_array_ben);
     assert_eq!(rotated_array_sisd, rotated_array_benk);
+    #[cfg(target_arch = "x86_64")]
+    for len in 0..100 {
+        let array = generate_array(len);
+        assert_eq!(double_array_lookup_u4(&array), double_array_clmul(&array));
+        assert_eq!(double_array_lookup_u4(&array), double_array_pdep(&array));
+        #[cfg(target_feature = "ssse3")]
+        assert_eq!(
+            double_array_lookup_u4(&array),
+            double_array_lookup_ssse3_u4(&array)
+        );
+        #[cfg(target_feature = "avx2")]
+        assert_eq!(
+            double_array_lookup_u4(&array),
+            double_array_lookup_avx2_u4(&array)
+        );
+        assert_eq!(double_array_lookup_u4(&array), double_bits(&array));
+    }
+    // regular and streaming stores, from every input alignment
+    #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+    for len in 0..300 {
+        let array = generate_array(len + 64);
+        for offset in 0..64 {
+            let array = &array[offset..offset + len];
+            let doubled = double_array_sisd(array);
+            for store in [StoreMode::Regular, StoreMode::Streaming, StoreMode::Auto] {
+                assert_eq!(doubled, double_array_lookup_ssse3_u4_with(array, store));
+                #[cfg(target_feature = "avx2")]
+                assert_eq!(doubled, double_array_lookup_avx2_u4_with(array, store));
+                #[cfg(all(
+                    target_feature = "avx512f",
+                    target_feature = "avx512vl",
+                    target_feature = "avx512bw",
+                    target_feature = "avx512vbmi",
+                    target_feature = "avx512vbmi2",
+                    target_feature = "avx512bitalg"
+                ))]
+                assert_eq!(doubled, double_array_lookup_avx512_u4_with(array, store));
+            }
+        }
+    }
+    // the same into slices, at every output alignment
+    for len in [0, 1, 15, 64, 100, 299] {
+        let array = generate_array(len + 64);
+        let mut doubled_array = vec![0; len * 2 + 128];
+        for offset in 0..64 {
+            let array = &array[offset..offset + len];
+            let doubled = double_array_sisd(array);
+            for out_offset in 0..128 {
+                let out = &mut doubled_array[out_offset..out_offset + len * 2];
+                for store in [StoreMode::Regular, StoreMode::Streaming, StoreMode::Auto] {
+                    out.fill(0);
+                    double_bits_slice_with(array, out, store);
+                    assert_eq!(doubled, out);
+                }
+            }
+        }
+    }
+    for len in [0, 1, 63, 64, 1000, 4099] {
+        let array = generate_aligned_array(len);
+        assert_eq!(array.as_ptr() as usize % CACHE_LINE_ALIGN, 0);
+        let doubled = double_bits_aligned(&array);
+        assert_eq!(doubled.as_ptr() as usize % CACHE_LINE_ALIGN, 0);
+        assert_eq!(&double_array_sisd(&array)[..], &doubled[..]);
+        let huge = AlignedBuf::zeroed_huge(len);
+        assert_eq!(huge.as_ptr() as usize % HUGE_PAGE_ALIGN, 0);
+        assert!(huge.iter().all(|&x| x == 0));
+        assert_eq!(huge.clone(), huge);
+    }
+    // backed by huge pages where the kernel hands them out on request
+    #[cfg(target_os = "linux")]
+    if std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")
+        .is_ok_and(|enabled| !enabled.contains("[never]"))
+    {
+        let huge = AlignedBuf::zeroed_huge(2 * HUGE_PAGE_ALIGN + 1);
+        assert!(anon_huge_pages(huge.as_ptr() as usize) >= 2 * HUGE_PAGE_ALIGN);
+    }
+    // the first call measures the threshold, later calls only read it
+    let calibrated = parallel_threshold();
+    assert!(calibrated >= 64 * 1024);
+    assert_eq!(calibrated, calibrate_parallel_threshold());
+    assert_eq!(calibrated, parallel_threshold());
+    // a set threshold wins over the calibration, even one that runs later
+    set_parallel_threshold(12345);
+    calibrate_parallel_threshold();
+    assert_eq!(parallel_threshold(), 12345);
+    // both sides of the threshold
+    for forced in [1, usize::MAX] {
+        set_parallel_threshold(forced);
+        for len in [0, 1, 100, 10_000] {
+            let array = generate_array(len);
+            assert_eq!(double_array_sisd(&array), double_bits(&array));
+        }
+    }
+    set_parallel_threshold(calibrated);
+    for len in 0..300 {
+        let array = generate_array(len);
+        let doubled = double_array_sisd(&array);
+        assert_eq!(doubled, double_array_portable_simd::<16>(&array));
+        assert_eq!(doubled, double_array_portable_simd::<32>(&array));
+        assert_eq!(doubled, double_array_portable_simd::<64>(&array));
+    }
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(3)
+        .build()
+        .unwrap();
+    for len in [0, 1, 63, 64, 65, 1000, 4099, 100_000] {
+        let array = generate_array(len);
+        let doubled = double_array_sisd(&array);
+        assert_eq!(doubled, double_bits_parallel(&array, None));
+        assert_eq!(doubled, double_bits_parallel(&array, Some(&thread_pool)));
+        assert_eq!(
+            doubled,
+            double_bits_parallel_with(&array, 3, double_array_lookup_u4_slice, Some(&thread_pool))
+        );
+    }
+
+    check_transpose();
+    check_bitplanes();
+    check_expand_depth();
+    check_upscale_bitmap();
+    check_multiply_bits();
+    check_resample();
+    check_formats();
+    check_font();
+    check_dsd();
+    check_reverse();
+    check_unpack();
+    check_parallel();
+    check_files();
+    check_streams();
+    check_pipeline();
+    check_threads();
+}
+
+fn scale_image(args: &[String]) {
+    let [input, output, rest @ ..] = args else {
+        eprintln!("usage: neon_test <input.pbm|input.xbm> <output.pbm|output.xbm> [scale]");
+        std::process::exit(1);
+    };
+    let scale = rest
+        .first()
+        .map_or(2, |scale| scale.parse().expect("invalid scale"));
+
+    let file = std::fs::File::open(input).expect("could not open input");
+    let bitmap = if input.ends_with(".xbm") {
+        formats::read_xbm(std::io::BufReader::new(file))
+    } else {
+        formats::read_pbm(std::io::BufReader::new(file))
+    }
+    .expect("could not read input");
+
+    let scaled = bitmap.scale(scale, scale);
+    let file = std::fs::File::create(output).expect("could not create output");
+    let mut writer = std::io::BufWriter::new(file);
+    if output.ends_with(".xbm") {
+        formats::write_xbm(&mut writer, &scaled, "scaled")
+    } else {
+        formats::write_pbm(&mut writer, &scaled)
+    }
+    .expect("could not write output");
+}
+
+/// compare every transpose implementation against the naive one
+fn check_transpose() {
+    let array = generate_array(64 * 8);
+    for block in array.chunks_exact(8) {
+        let x = u64::from_be_bytes(block.try_into().unwrap());
+        assert_eq!(transpose_8x8_naive(x), transpose_8x8(x));
+    }
+
+    let mut matrix_16: [u16; 16] =
+        std::array::from_fn(|i| u16::from_be_bytes([array[i * 2], array[i * 2 + 1]]));
+    let bytes_16: Vec<u8> = matrix_16.iter().flat_map(|row| row.to_be_bytes()).collect();
+    transpose_16x16(&mut matrix_16);
+    let transposed_16: Vec<u8> = matrix_16.iter().flat_map(|row| row.to_be_bytes()).collect();
+    assert_eq!(transpose_bits_naive(&bytes_16, 16, 2), transposed_16);
+
+    let mut matrix_64: [u64; 64] =
+        std::array::from_fn(|i| u64::from_be_bytes(array[i * 8..i * 8 + 8].try_into().unwrap()));
+    let bytes_64: Vec<u8> = matrix_64.iter().flat_map(|row| row.to_be_bytes()).collect();
+    transpose_64x64(&mut matrix_64);
+    let transposed_64: Vec<u8> = matrix_64.iter().flat_map(|row| row.to_be_bytes()).collect();
+    assert_eq!(transpose_bits_naive(&bytes_64, 64, 8), transposed_64);
+
+    for rows in 0..70 {
+        for row_bytes in 0..6 {
+            let array = generate_array(rows * row_bytes);
+            let naive = transpose_bits_naive(&array, rows, row_bytes);
+            assert_eq!(naive, transpose_bits_swar(&array, rows, row_bytes));
+            assert_eq!(naive, transpose_bits_simd(&array, rows, row_bytes));
+            #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+            assert_eq!(naive, transpose_bits_sse2(&array, rows, row_bytes));
+            #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+            assert_eq!(naive, transpose_bits_avx2(&array, rows, row_bytes));
+        }
+    }
+}
+
+/// round trip through the bit planes and compare them bit by bit with the input
+fn check_bitplanes() {
+    for lanes in 1..40 {
+        for steps in 0..20 {
+            let array = generate_array(lanes * steps);
+            let planes = to_bitplanes(&array, lanes);
+            let lane_bytes = lanes.div_ceil(8);
+            for step in 0..steps {
+                for lane in 0..lanes {
+                    for (plane, bits) in planes.iter().enumerate() {
+                        let bit = (array[step * lanes + lane] >> (7 - plane)) & 1;
+                        let plane_bit = (bits[step * lane_bytes + lane / 8] >> (7 - lane % 8)) & 1;
+                        assert_eq!(bit, plane_bit);
+                    }
+                }
+            }
+            assert_eq!(array, from_bitplanes(&planes, lanes));
+        }
+    }
+}
+
+/// compare the depth expansion against a pixel by pixel reference
+fn check_expand_depth() {
+    let array = generate_array(100);
+    assert_eq!(
+        double_array_sisd(&array),
+        expand_depth(&array, 1, 2, &[0, 3])
+    );
+
+    for (from_bpp, to_bpp) in [(1, 2), (1, 4), (1, 8), (2, 4), (2, 8), (4, 8)] {
+        let palette: Vec<u8> = generate_array(1 << from_bpp)
+            .iter()
+            .map(|&value| (value as u16 % (1 << to_bpp)) as u8)
+            .collect();
+        let mut expected = vec![0u8; array.len() * to_bpp / from_bpp];
+        for pixel in 0..array.len() * 8 / from_bpp {
+            let bit = pixel * from_bpp;
+            let index = (array[bit / 8] >> (8 - from_bpp - bit % 8)) & ((1 << from_bpp) - 1);
+            let bit = pixel * to_bpp;
+            expected[bit / 8] |= palette[index as usize] << (8 - to_bpp - bit % 8);
+        }
+        assert_eq!(
+            expected,
+            expand_depth_lookup_u4(&array, from_bpp, to_bpp, &palette)
+        );
+        assert_eq!(
+            expected,
+            expand_depth_lookup_u8(&array, from_bpp, to_bpp, &palette)
+        );
+        assert_eq!(expected, expand_depth(&array, from_bpp, to_bpp, &palette));
+    }
+}
+
+/// nearest neighbour scaling of a 1bpp bitmap, one pixel at a time
+fn scale_bitmap_naive(
+    array: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    scale_x: usize,
+    scale_y: usize,
+) -> (Vec<u8>, usize) {
+    let dst_stride = (width * scale_x).div_ceil(8);
+    let mut scaled = vec![0; dst_stride * height * scale_y];
+    for y in 0..height * scale_y {
+        for x in 0..width * scale_x {
+            let (src_x, src_y) = (x / scale_x, y / scale_y);
+            let bit = (array[src_y * stride + src_x / 8] >> (7 - src_x % 8)) & 1;
+            scaled[y * dst_stride + x / 8] |= bit << (7 - x % 8);
+        }
+    }
+    (scaled, dst_stride)
+}
+
+/// upscale bitmaps with random padding bits and strides
+fn check_upscale_bitmap() {
+    for width in 0..40usize {
+        for height in 0..5 {
+            for extra_stride in 0..3 {
+                let stride = width.div_ceil(8) + extra_stride;
+                let array = generate_array(stride * height);
+                assert_eq!(
+                    scale_bitmap_naive(&array, width, height, stride, 2, 2),
+                    upscale_bitmap_2x(&array, width, height, stride)
+                );
+                for (scale_x, scale_y) in [(1, 1), (2, 3), (3, 3), (4, 4), (5, 1), (8, 2)] {
+                    assert_eq!(
+                        scale_bitmap_naive(&array, width, height, stride, scale_x, scale_y),
+                        scale_bitmap(&array, width, height, stride, scale_x, scale_y)
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// every N-fold kernel against the bit by bit definition
+fn check_multiply_bits() {
+    let array = generate_array(100);
+    // the last ones are past the factors whose tables are kept
+    for n in (1..12).chain([64, 65, 100]) {
+        let mut expected = vec![0u8; array.len() * n];
+        for bit in 0..array.len() * 8 {
+            let value = (array[bit / 8] >> (7 - bit % 8)) & 1;
+            for k in 0..n {
+                let pos = bit * n + k;
+                expected[pos / 8] |= value << (7 - pos % 8);
+            }
+        }
+        if n.is_multiple_of(2) {
+            assert_eq!(expected, multiply_array_lookup_u4(&array, n));
+        }
+        assert_eq!(expected, multiply_array_lookup_u8(&array, n));
+        assert_eq!(expected, multiply_bits(&array, n));
+    }
+}
+
+/// rational resampling against the naive version, for table, schedule and integer paths
+fn check_resample() {
+    let array = generate_array(300);
+    assert_eq!(
+        double_array_sisd(&array),
+        resample_bits(&array, array.len() * 8, 2, 1)
+    );
+    assert_eq!(
+        double_array_sisd(&array),
+        resample_bits(&array, array.len() * 8, 6, 3)
+    );
+
+    for (num, den) in [
+        (3, 2),
+        (5, 3),
+        (2, 3),
+        (1, 1),
+        (3, 1),
+        (7, 8),
+        (17, 2),
+        (3, 1000),
+        (1001, 1000),
+    ] {
+        for bit_len in [0, 1, 7, 8, 9, 100, 2399, 2400] {
+            assert_eq!(
+                resample_bits_naive(&array, bit_len, num, den),
+                resample_bits(&array, bit_len, num, den),
+                "{num}/{den}, {bit_len} bits"
+            );
+        }
+    }
+
+    for width in [0usize, 1, 13, 64, 100] {
+        for height in [0, 1, 5, 9] {
+            let stride = width.div_ceil(8) + 1;
+            for ((num_x, den_x), (num_y, den_y)) in [
+                ((3, 2), (3, 2)),
+                ((5, 3), (1, 1)),
+                ((2, 1), (3, 1)),
+                ((1, 2), (2, 3)),
+            ] {
+                let (resampled, dst_stride) = resample_bitmap(
+                    &array,
+                    width,
+                    height,
+                    stride,
+                    (num_x, den_x),
+                    (num_y, den_y),
+                );
+                let dst_height = resampled_len(height, num_y, den_y);
+                assert_eq!(dst_stride, resampled_len(width, num_x, den_x).div_ceil(8));
+                assert_eq!(resampled.len(), dst_stride * dst_height);
+                for row in 0..dst_height {
+                    let src_row = row * den_y / num_y;
+                    assert_eq!(
+                        resample_bits_naive(&array[src_row * stride..], width, num_x, den_x),
+                        &resampled[row * dst_stride..(row + 1) * dst_stride]
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// write and read back random bitmaps in every format
+fn check_formats() {
+    for (width, height) in [(0, 0), (1, 1), (7, 3), (8, 2), (13, 5), (100, 7)] {
+        let array = generate_array(width * height);
+        let mut bitmap = Bitmap::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                bitmap.set(x, y, array[y * width + x] & 1 == 1);
+            }
+        }
+
+        let mut pbm = Vec::new();
+        formats::write_pbm(&mut pbm, &bitmap).unwrap();
+        assert_eq!(bitmap, formats::read_pbm(&pbm[..]).unwrap());
+
+        let mut pbm_ascii = Vec::new();
+        formats::write_pbm_ascii(&mut pbm_ascii, &bitmap).unwrap();
+        assert_eq!(bitmap, formats::read_pbm(&pbm_ascii[..]).unwrap());
+
+        let mut xbm = Vec::new();
+        formats::write_xbm(&mut xbm, &bitmap, "test").unwrap();
+        assert_eq!(bitmap, formats::read_xbm(&xbm[..]).unwrap());
+
+        let scaled = formats::read_pbm(&pbm[..]).unwrap().upscale_2x();
+        for y in 0..height * 2 {
+            for x in 0..width * 2 {
+                assert_eq!(bitmap.get(x / 2, y / 2), scaled.get(x, y));
+            }
+        }
+    }
+
+    let pbm = b"P1\n# comment\n4 2\n1 0 1 1\n0110\n";
+    let bitmap = formats::read_pbm(&pbm[..]).unwrap();
+    assert_eq!(bitmap.data, [0b1011_0000, 0b0110_0000]);
+
+    let xbm = "#define x_width 10\n#define x_height 1\nstatic char x_bits[] = { 0x01, 0x03 };\n";
+    let bitmap = formats::read_xbm(xbm.as_bytes()).unwrap();
+    assert_eq!(bitmap.data, [0b1000_0000, 0b1100_0000]);
+
+    assert!(formats::read_pbm(&b"P2\n1 1\n0\n"[..]).is_err());
+    assert!(formats::read_pbm(&b"P4\n16 2\n\x00"[..]).is_err());
+    // sizes from the header that overflow or are far bigger than the file are errors, not
+    // allocations
+    for header in [
+        "P4\n18446744073709551615 18446744073709551615\n",
+        "P4\n100000 100000\n",
+        "P1\n18446744073709551615 2\n",
+        "P1\n100000 100000\n0 1",
+        "P1\n0 18446744073709551615\n",
+        "P4\n18446744073709551615 0\n",
+    ] {
+        let error = formats::read_pbm(header.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+    let xbm =
+        "#define x_width 18446744073709551615\n#define x_height 9\nstatic char x_bits[] = { 0 };\n";
+    let error = formats::read_xbm(xbm.as_bytes()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    let xbm =
+        "#define x_width 0\n#define x_height 18446744073709551615\nstatic char x_bits[] = { };\n";
+    let error = formats::read_xbm(xbm.as_bytes()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+/// render text with a tiny synthetic font and compare it with the glyphs
+fn check_font() {
+    let bdf = "STARTFONT 2.1
+FONT -test-
+SIZE 4 75 75
+FONTBOUNDINGBOX 3 4 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 3
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 3
+STARTCHAR A
+ENCODING 65
+DWIDTH 4 0
+BBX 3 3 0 0
+BITMAP
+40
+A0
+E0
+ENDCHAR
+STARTCHAR g
+ENCODING 103
+DWIDTH 3 0
+BBX 2 3 0 -1
+BITMAP
+C0
+40
+C0
+ENDCHAR
+STARTCHAR unencoded
+ENCODING -1
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+ENDFONT
+";
+    let font = font::BdfFont::parse(bdf.as_bytes()).unwrap();
+    assert_eq!(font.glyphs.len(), 2);
+
+    let line = font::render_text(&font, "AgA?", 1);
+    #[rustfmt::skip]
+    let expected = [
+        ".#......#..",
+        "#.#.##.#.#.",
+        "###..#.###.",
+        "....##.....",
+    ];
+    assert_eq!((line.width, line.height), (11, 4));
+    for (y, row) in expected.iter().enumerate() {
+        for (x, pixel) in row.chars().enumerate() {
+            assert_eq!(pixel == '#', line.get(x, y), "pixel {x}, {y}");
+        }
+    }
+
+    for scale in 2..5 {
+        assert_eq!(
+            line.scale(scale, scale),
+            font::render_text(&font, "AgA?", scale)
+        );
+    }
+}
+
+/// round trip synthetic DSF and DFF files and upsample them
+fn check_dsd() {
+    use dsd::DsdAudio;
+
+    // more than one DSF block per channel and a partial last byte
+    let sample_count = 4096 * 8 * 2 + 13;
+    let channel_bytes = (sample_count as usize).div_ceil(8);
+    let array = generate_array(channel_bytes * 2);
+    let mut channels: Vec<Vec<u8>> = array
+        .chunks_exact(channel_bytes)
+        .map(|c| c.to_vec())
+        .collect();
+    for channel in &mut channels {
+        *channel.last_mut().unwrap() &= 0b1111_1000;
+    }
+    let audio = DsdAudio {
+        sample_rate: 2_822_400,
+        sample_count,
+        channels,
+    };
+
+    let mut dsf = Vec::new();
+    audio.write_dsf(&mut dsf).unwrap();
+    assert_eq!(dsf.len(), 28 + 52 + 12 + 3 * 4096 * 2);
+    // the first block is channel 0, LSB-first
+    assert_eq!(dsf[28 + 52 + 12], audio.channels[0][0].reverse_bits());
+    assert_eq!(
+        dsf[28 + 52 + 12 + 4096],
+        audio.channels[1][0].reverse_bits()
+    );
+    assert_eq!(audio, DsdAudio::read(&dsf[..]).unwrap());
+
+    let upsampled = audio.upsample(2).unwrap();
+    assert_eq!(upsampled.sample_rate, 2 * 2_822_400);
+    assert_eq!(upsampled.sample_count, 2 * sample_count);
+    for (channel, upsampled) in audio.channels.iter().zip(&upsampled.channels) {
+        assert_eq!(&double_array_sisd(channel), upsampled);
+    }
+    let mut dsf = Vec::new();
+    upsampled.write_dsf(&mut dsf).unwrap();
+    assert_eq!(upsampled, DsdAudio::read_dsf(&dsf[..]).unwrap());
+
+    // DFF only holds whole bytes per channel
+    let audio = DsdAudio {
+        sample_rate: 2_822_400,
+        sample_count: 8 * 1001,
+        channels: (0..3)
+            .map(|i| generate_array(1001 + i)[i..].to_vec())
+            .collect(),
+    };
+    let mut dff = Vec::new();
+    audio.write_dff(&mut dff).unwrap();
+    assert_eq!(&dff[..4], b"FRM8");
+    assert_eq!(
+        u64::from_be_bytes(dff[4..12].try_into().unwrap()) as usize,
+        dff.len() - 12
+    );
+    assert_eq!(audio, DsdAudio::read(&dff[..]).unwrap());
+
+    let upsampled = audio.upsample(4).unwrap();
+    for (channel, upsampled) in audio.channels.iter().zip(&upsampled.channels) {
+        assert_eq!(&multiply_array_lookup_u8(channel, 4), upsampled);
+    }
+    let mut dff = Vec::new();
+    upsampled.write_dff(&mut dff).unwrap();
+    assert_eq!(upsampled, DsdAudio::read_dff(&dff[..]).unwrap());
+    // audio built by hand is checked before upsampling, not trusted
+    let short = DsdAudio {
+        sample_count: audio.sample_count + 8,
+        ..audio.clone()
+    };
+    let too_fast = DsdAudio {
+        sample_rate: u32::MAX / 2,
+        ..audio.clone()
+    };
+    for (audio, factor) in [
+        (&short, 2),
+        (&too_fast, 4),
+        (&audio, 0),
+        (&audio, usize::MAX),
+    ] {
+        let error = audio.upsample(factor).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    assert!(DsdAudio::read(&b"RIFF"[..]).is_err());
+    assert!(DsdAudio::read_dsf(&dsf[..100]).is_err());
+    // sizes from the fmt chunk that overflow or are far bigger than the file are errors
+    let patched = |offset: usize, value: &[u8]| {
+        let mut dsf = dsf[..120].to_vec();
+        dsf[offset..offset + value.len()].copy_from_slice(value);
+        DsdAudio::read_dsf(&dsf[..]).unwrap_err().kind()
+    };
+    let invalid_data = std::io::ErrorKind::InvalidData;
+    // fmt chunk size
+    assert_eq!(patched(36, &u64::MAX.to_le_bytes()), invalid_data);
+    // channel count and sample count
+    assert_eq!(patched(56, &u32::MAX.to_le_bytes()), invalid_data);
+    assert_eq!(patched(68, &u64::MAX.to_le_bytes()), invalid_data);
+    assert_eq!(patched(68, &(1u64 << 40).to_le_bytes()), invalid_data);
+}
+
+/// compare the reversals against the reverse_bits of the integer types
+fn check_reverse() {
+    for len in 0..100 {
+        let array = generate_array(len * 8);
+        let bytes: Vec<u8> = array.iter().map(|x| x.reverse_bits()).collect();
+        assert_eq!(bytes, reverse_bits_in_bytes_lookup(&array));
+        assert_eq!(bytes, reverse_bits_in_bytes(&array));
+
+        let words: Vec<u8> = array
+            .chunks_exact(2)
+            .flat_map(|word| {
+                u16::from_be_bytes(word.try_into().unwrap())
+                    .reverse_bits()
+                    .to_be_bytes()
+            })
+            .collect();
+        assert_eq!(words, reverse_bits_in_words_lookup::<u16>(&array));
+        assert_eq!(words, reverse_bits_in_words::<u16>(&array));
+
+        let words: Vec<u8> = array
+            .chunks_exact(4)
+            .flat_map(|word| {
+                u32::from_be_bytes(word.try_into().unwrap())
+                    .reverse_bits()
+                    .to_be_bytes()
+            })
+            .collect();
+        assert_eq!(words, reverse_bits_in_words_lookup::<u32>(&array));
+        assert_eq!(words, reverse_bits_in_words::<u32>(&array));
+
+        let words: Vec<u8> = array
+            .chunks_exact(8)
+            .flat_map(|word| {
+                u64::from_be_bytes(word.try_into().unwrap())
+                    .reverse_bits()
+                    .to_be_bytes()
+            })
+            .collect();
+        assert_eq!(words, reverse_bits_in_words_lookup::<u64>(&array));
+        assert_eq!(words, reverse_bits_in_words::<u64>(&array));
+
+        // odd lengths for the whole buffer, so the tail is not a whole chunk
+        let array = &array[..len * 3];
+        let buffer: Vec<u8> = array.iter().rev().map(|x| x.reverse_bits()).collect();
+        assert_eq!(buffer, reverse_bits_buffer_lookup(array));
+        assert_eq!(buffer, reverse_bits_buffer(array));
+        assert_eq!(array, reverse_bits_buffer(&buffer));
+    }
+}
+
+/// compare unpacking bit by bit with the input, and pack it back
+fn check_unpack() {
+    for len in 0..70 {
+        let array = generate_array(len);
+        for true_value in [0x01, 0xff, 0x5a] {
+            let unpacked: Vec<u8> = (0..len * 8)
+                .map(|i| ((array[i / 8] >> (7 - i % 8)) & 1) * true_value)
+                .collect();
+            assert_eq!(unpacked, unpack_bits_sisd(&array, true_value));
+            assert_eq!(unpacked, unpack_bits(&array, true_value));
+            #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+            assert_eq!(unpacked, unpack_bits_ssse3(&array, true_value));
+            #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+            assert_eq!(unpacked, unpack_bits_avx2(&array, true_value));
+
+            assert_eq!(array, pack_bits_sisd(&unpacked));
+            assert_eq!(array, pack_bits(&unpacked));
+            #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+            assert_eq!(array, pack_bits_ssse3(&unpacked));
+            #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+            assert_eq!(array, pack_bits_avx2(&unpacked));
+        }
+        assert_eq!(array, pack_bools(&unpack_bools(&array)));
+
+        // bytes that are not a whole number of bits, any non-zero byte is set
+        let bytes = generate_array(len * 3);
+        let packed: Vec<u8> = bytes
+            .chunks(8)
+            .map(|chunk| {
+                let bits = chunk
+                    .iter()
+                    .fold(0u8, |acc, &x| (acc << 1) | (x != 0) as u8);
+                bits << (8 - chunk.len())
+            })
+            .collect();
+        assert_eq!(packed, pack_bits_sisd(&bytes));
+        assert_eq!(packed, pack_bits(&bytes));
+    }
+}
+
+/// every length with every thread count, so ragged tails and chunks shorter than a cache line
+/// all have to come out right
+fn check_parallel() {
+    let thread_pools: Vec<rayon::ThreadPool> = (1..16)
+        .map(|threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        })
+        .collect();
+    for len in 0..10_000 {
+        let array = generate_array(len);
+        let doubled = double_array_sisd(&array);
+        for thread_pool in &thread_pools {
+            assert_eq!(doubled, double_bits_parallel(&array, Some(thread_pool)));
+            assert_eq!(
+                doubled,
+                double_bits_parallel_with(
+                    &array,
+                    1,
+                    double_array_sisd_opt_slice,
+                    Some(thread_pool)
+                )
+            );
+        }
+    }
+}
+
+/// Bytes of the mapping around `address` that are backed by transparent huge pages.
+#[cfg(target_os = "linux")]
+fn anon_huge_pages(address: usize) -> usize {
+    let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+    let mut inside = false;
+    for line in smaps.lines() {
+        let range = line.split_whitespace().next().and_then(|range| {
+            let (start, end) = range.split_once('-')?;
+            Some((
+                usize::from_str_radix(start, 16).ok()?,
+                usize::from_str_radix(end, 16).ok()?,
+            ))
+        });
+        if let Some((start, end)) = range {
+            inside = (start..end).contains(&address);
+        } else if let Some(kb) = line.strip_prefix("AnonHugePages:").filter(|_| inside) {
+            return kb
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<usize>()
+                .unwrap()
+                * 1024;
+        }
+    }
+    0
+}
+
+/// double and halve temp files of odd sizes, mapped and buffered
+fn check_files() {
+    let dir = std::env::temp_dir().join(format!("neon_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input");
+    let doubled = dir.join("doubled");
+    let halved = dir.join("halved");
+
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(3)
+        .build()
+        .unwrap();
+    for len in [0, 1, 4095, 4097, 3 * 65536 + 7, (1 << 20) + 1] {
+        let array = generate_array(len);
+        assert_eq!(array, halve_bits(&double_array_sisd(&array)));
+        std::fs::write(&input, &array).unwrap();
+        for (mmap, sync) in [(true, false), (true, true), (false, false), (false, true)] {
+            let options = FileOptions {
+                mmap,
+                sync,
+                // a few reads for the bigger files
+                buffer_size: 100_000,
+                thread_pool: Some(&thread_pool),
+            };
+            double_file(&input, &doubled, &options).unwrap();
+            assert_eq!(double_array_sisd(&array), std::fs::read(&doubled).unwrap());
+            halve_file(&doubled, &halved, &options).unwrap();
+            assert_eq!(array, std::fs::read(&halved).unwrap());
+        }
+    }
+
+    // an odd length cannot have been doubled
+    std::fs::write(&input, [0; 3]).unwrap();
+    let error = halve_file(&input, &halved, &FileOptions::default()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    // the input must not be truncated before it is read, however it is named
+    let array = generate_array(1000);
+    std::fs::write(&input, &array).unwrap();
+    let link = dir.join("link");
+    std::fs::hard_link(&input, &link).unwrap();
+    for output in [&input, &dir.join(".").join("input"), &link] {
+        let error = double_file(&input, output, &FileOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(array, std::fs::read(&input).unwrap());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Hands out at most a few bytes per call and is interrupted every now and then, like a socket.
+struct Trickle<T> {
+    inner: T,
+    calls: usize,
+}
+
+impl<T> Trickle<T> {
+    fn new(inner: T) -> Self {
+        Trickle { inner, calls: 0 }
+    }
+
+    /// How many bytes the next call may move, `None` to interrupt it.
+    fn next_len(&mut self, len: usize) -> Option<usize> {
+        self.calls += 1;
+        if self.calls.is_multiple_of(5) {
+            None
+        } else {
+            Some(usize::min(len, self.calls % 7 + 1))
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for Trickle<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.next_len(buf.len()) {
+            Some(len) => self.inner.write(&buf[..len]),
+            None => Err(std::io::ErrorKind::Interrupted.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for Trickle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.next_len(buf.len()) {
+            Some(len) => self.inner.read(&mut buf[..len]),
+            None => Err(std::io::ErrorKind::Interrupted.into()),
+        }
+    }
+}
+
+/// stream through the io adapters with odd block sizes, short reads and writes
+fn check_streams() {
+    use std::io::{BufRead, Read, Write};
+
+    for len in [0, 1, 2, 7, 100, 4097] {
+        let array = generate_array(len);
+        let doubled = double_array_sisd(&array);
+        for block_size in [0, 1, 3, 64, 1000, DEFAULT_BLOCK_SIZE] {
+            // writes of every size, from single bytes to all at once
+            for write_len in [1, 5, 333, len.max(1)] {
+                let mut writer =
+                    DoublingWriter::with_block_size(Trickle::new(Vec::new()), block_size);
+                for chunk in array.chunks(write_len) {
+                    writer.write_all(chunk).unwrap();
+                }
+                assert_eq!(doubled, writer.into_inner().unwrap().inner);
+            }
+            // dropping writes out what is left
+            let mut output = Vec::new();
+            DoublingWriter::with_block_size(&mut output, block_size)
+                .write_all(&array)
+                .unwrap();
+            assert_eq!(doubled, output);
+
+            let mut reader = DoublingReader::with_block_size(Trickle::new(&array[..]), block_size);
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            assert_eq!(doubled, output);
+
+            // big reads go around the buffer
+            let mut reader = DoublingReader::with_block_size(&array[..], block_size);
+            let mut output = vec![0; len * 2 + 10];
+            let mut filled = 0;
+            loop {
+                let read = reader.read(&mut output[filled..]).unwrap();
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+            assert_eq!(doubled, output[..filled]);
+
+            for mode in [HalvingMode::Strict, HalvingMode::Lenient] {
+                let mut writer =
+                    HalvingWriter::with_block_size(Trickle::new(Vec::new()), mode, block_size);
+                for chunk in doubled.chunks(3) {
+                    writer.write_all(chunk).unwrap();
+                }
+                assert_eq!(array, writer.into_inner().unwrap().inner);
+
+                let mut reader =
+                    HalvingReader::with_block_size(Trickle::new(&doubled[..]), mode, block_size);
+                let mut output = Vec::new();
+                reader.read_to_end(&mut output).unwrap();
+                assert_eq!(array, output);
+            }
+        }
+    }
+
+    // blocks fill up however little the inner reader hands out at a time
+    let array = generate_array(1000);
+    let mut reader = DoublingReader::with_block_size(Trickle::new(&array[..]), 300);
+    assert_eq!(600, reader.fill_buf().unwrap().len());
+    let mut reader =
+        HalvingReader::with_block_size(Trickle::new(&array[..]), HalvingMode::Lenient, 300);
+    assert_eq!(150, reader.fill_buf().unwrap().len());
+
+    // lenient halving keeps the first bit of every pair
+    let array = generate_array(1001);
+    let mut output = Vec::new();
+    HalvingReader::with_block_size(&array[..1000], HalvingMode::Lenient, 77)
+        .read_to_end(&mut output)
+        .unwrap();
+    assert_eq!(halve_bits(&array[..1000]), output);
+    // odd lengths cannot have been doubled
+    let mut writer = HalvingWriter::new(Vec::new(), HalvingMode::Lenient);
+    writer.write_all(&array).unwrap();
+    let error = writer.into_inner().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    let error = HalvingReader::new(&array[..], HalvingMode::Lenient)
+        .read_to_end(&mut Vec::new())
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    // strict halving stops at the first pair that differs, with its offset in the stream
+    let array = generate_array(500);
+    let doubled = double_array_sisd(&array);
+    assert_eq!(find_invalid_pair(&doubled), None);
+    for bit in (0..doubled.len() * 8).step_by(13) {
+        let mut broken = doubled.clone();
+        broken[bit / 8] ^= 0x80 >> (bit % 8);
+        // a second invalid pair further on must not be the one reported
+        *broken.last_mut().unwrap() ^= 1;
+        let pair = (bit & !1) as u64;
+        assert_eq!(find_invalid_pair(&broken), Some(bit & !1));
+        let bit_offset = |error: std::io::Error| {
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            error
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<InvalidPair>())
+                .unwrap()
+                .bit_offset
+        };
+
+        let mut writer =
+            HalvingWriter::with_block_size(Trickle::new(Vec::new()), HalvingMode::Strict, 64);
+        for chunk in broken.chunks(5) {
+            if let Err(error) = writer.write_all(chunk) {
+                assert_eq!(bit_offset(error), pair);
+                break;
+            }
+        }
+        writer.flush().unwrap();
+        assert_eq!(array[..bit / 16], writer.get_ref().inner);
+
+        let mut reader =
+            HalvingReader::with_block_size(Trickle::new(&broken[..]), HalvingMode::Strict, 64);
+        let mut output = Vec::new();
+        let mut buf = [0; 7];
+        let error = loop {
+            match reader.read(&mut buf) {
+                Ok(0) => panic!("the invalid pair was not reported"),
+                Ok(len) => output.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(bit_offset(error), pair);
+        assert_eq!(array[..bit / 16], output);
+    }
+}
+
+/// run the pipeline with few and many workers and blocks, and stop it from either end
+fn check_pipeline() {
+    use std::io::Read;
+
+    for len in [0, 1, 1000, 100_003] {
+        let array = generate_array(len);
+        let doubled = double_array_sisd(&array);
+        for workers in [1, 2, 5] {
+            for block_size in [1, 7, 4096, DEFAULT_BLOCK_SIZE] {
+                let mut output = Vec::new();
+                let stats = Pipeline::new(&array[..], &mut output)
+                    .workers(workers)
+                    .block_size(block_size)
+                    .run()
+                    .unwrap();
+                assert_eq!(doubled, output);
+                assert_eq!(stats.input_bytes, len as u64);
+                assert_eq!(stats.output_bytes, len as u64 * 2);
+                assert_eq!(stats.blocks, len.div_ceil(block_size) as u64);
+            }
+            // short reads and writes and interruptions on both ends
+            let mut output = Trickle::new(Vec::new());
+            Pipeline::new(Trickle::new(&array[..]), &mut output)
+                .workers(workers)
+                .block_size(100)
+                .run()
+                .unwrap();
+            assert_eq!(doubled, output.inner);
+        }
+    }
+
+    let array = generate_array(10_000);
+    // a writer that fills up stops the reader and the workers
+    let mut output = [0; 5000];
+    let error = Pipeline::new(&array[..], &mut output[..])
+        .workers(3)
+        .block_size(64)
+        .run()
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::WriteZero);
+    assert_eq!(output[..], double_array_sisd(&array[..2500]));
+    // a reader that fails stops the workers and the writer, after what it read was written
+    let reader = (&array[..3000]).chain(FailingReader);
+    let mut output = Vec::new();
+    let error = Pipeline::new(reader, &mut output)
+        .workers(3)
+        .block_size(64)
+        .run()
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+    assert_eq!(output, double_array_sisd(&array[..2944]));
+}
+
+struct FailingReader;
+
+impl std::io::Read for FailingReader {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+/// cores the process may run on, pinning to any other one fails
+#[cfg(target_os = "linux")]
+fn allowed_cores() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        assert_eq!(
+            libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set),
+            0
+        );
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&core| libc::CPU_ISSET(core, &set))
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cores() -> Vec<usize> {
+    Vec::new()
+}
+
+/// dedicated pools and pipeline workers at a low priority, pinned to up to two cores
+fn check_threads() {
+    let mut cores = allowed_cores();
+    cores.truncate(2);
+    let config = ThreadConfig {
+        threads: Some(3),
+        priority: Some(ThreadPriority::Min),
+        cores,
+    };
+    assert_eq!(config.thread_count(), 3);
+    let thread_pool = config.build_pool().unwrap();
+    assert_eq!(thread_pool.current_num_threads(), 3);
+    #[cfg(target_os = "linux")]
+    for (index, core) in
+        thread_pool.broadcast(|context| (context.index(), unsafe { libc::sched_getcpu() }))
+    {
+        assert_eq!(core as usize, config.cores[index % config.cores.len()]);
+    }
+
+    let array = generate_array(100_000);
+    let doubled = double_array_sisd(&array);
+    assert_eq!(doubled, double_bits_parallel(&array, Some(&thread_pool)));
+    let mut output = Vec::new();
+    let stats = Pipeline::new(&array[..], &mut output)
+        .thread_config(config)
+        .block_size(1000)
+        .run()
+        .unwrap();
+    assert_eq!(doubled, output);
+    assert_eq!(stats.blocks, 100);
+
+    // a core that does not exist fails the pool and every worker, but not the process
+    #[cfg(target_os = "linux")]
+    {
+        let config = ThreadConfig {
+            threads: Some(2),
+            cores: vec![1 << 20],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.build_pool().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        let error = Pipeline::new(&array[..], std::io::sink())
+            .thread_config(config)
+            .run()
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        // neither read nor written, even though the other worker was set up
+        let config = ThreadConfig {
+            threads: Some(2),
+            cores: vec![allowed_cores()[0], 1 << 20],
+            ..Default::default()
+        };
+        let mut input = &array[..];
+        let mut output = Vec::new();
+        let error = Pipeline::new(&mut input, &mut output)
+            .thread_config(config)
+            .run()
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(input.len(), array.len());
+        assert!(output.is_empty());
+    }
 }
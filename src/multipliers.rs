@@ -23,9 +23,9 @@ pub fn double_array_benk(array: &[u8]) -> Vec<u8> {
     let mut doubled_array = vec![0; array.len() * 2];
 
     for i in (0..array.len()).step_by(4) {
-        let num = u32::from_le_bytes([array[i], array[i + 1], array[i + 2], array[i + 3]]);
+        let num = u32::from_be_bytes([array[i], array[i + 1], array[i + 2], array[i + 3]]);
         let num = double(num);
-        let num_array = num.to_le_bytes();
+        let num_array = num.to_be_bytes();
         doubled_array[i * 2..i * 2 + 8].copy_from_slice(&num_array);
     }
     doubled_array
@@ -1,6 +1,4 @@
-use rayon::prelude::{ParallelBridge, ParallelIterator};
-
-pub fn double_array_sisd_opt(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_sisd_opt(array: &[u8]) -> Vec<u8> {
     let size = array.len();
     // let mut doubled_array = vec![0; size * 2];
     let mut doubled_array = Box::new_uninit_slice(size * 2);
@@ -39,39 +37,26 @@ pub fn double_array_sisd_opt_iter(array: &[u8]) -> Vec<u8> {
         .collect()
 }
 
-pub fn double_array_sisd_opt_rayon(array: &[u8]) -> Vec<u8> {
-    let num_chunks = 8;
-    let chunk_len = array.len() / num_chunks;
-    let size = array.len();
-    // let array = &array[..size];
-    // let mut doubled_array = vec![0; size * 2];
-    let doubled_array = std::cell::SyncUnsafeCell::new(Box::new_uninit_slice(size * 2));
+/// Same as `double_array_sisd_opt`, but writes into `doubled_array`.
+pub fn double_array_sisd_opt_slice(array: &[u8], doubled_array: &mut [u8]) {
+    assert_eq!(array.len() * 2, doubled_array.len());
 
-    (0..num_chunks)
-        .map(|i| i * chunk_len)
-        .par_bridge()
-        .for_each(|start| {
-            let end = usize::min(start + chunk_len, size);
-            for i in start..end {
-                let num: u16 = array[i] as u16;
-                let num = num & 0b0000_0000_1111_1111 | (num & 0b1111_1111_0000_0000) << 8;
-                let num = num & 0b0000_1111_0000_1111 | (num & 0b1111_0000_1111_0000) << 4;
-                let num = num & 0b0011_0011_0011_0011 | (num & 0b1100_1100_1100_1100) << 2;
-                let num = num & 0b0101_0101_0101_0101 | (num & 0b1010_1010_1010_1010) << 1;
-                let num = num | num << 1;
-                // doubled_array[i * 2 + 1] = (num & 0b0000_0000_1111_1111) as u8;
-                // doubled_array[i * 2] = ((num & 0b1111_1111_0000_0000) >> 8) as u8;
-                let doubled_array = unsafe { &mut *doubled_array.get() };
-                doubled_array[i * 2 + 1].write((num & 0b0000_0000_1111_1111) as u8);
-                doubled_array[i * 2].write(((num & 0b1111_1111_0000_0000) >> 8) as u8);
-            }
-        });
-    // SAFETY: we just wrote to every element of the array
-    let array = unsafe { doubled_array.into_inner().assume_init() };
-    Vec::from(array)
+    for (&x, doubled) in array.iter().zip(doubled_array.chunks_exact_mut(2)) {
+        let num: u16 = x as u16;
+        let num = num & 0b0000_1111_0000_1111 | (num & 0b1111_0000_1111_0000) << 4;
+        let num = num & 0b0011_0011_0011_0011 | (num & 0b1100_1100_1100_1100) << 2;
+        let num = num & 0b0101_0101_0101_0101 | (num & 0b1010_1010_1010_1010) << 1;
+        let num = num | num << 1;
+        doubled.copy_from_slice(&num.to_be_bytes());
+    }
+}
+
+/// `double_array_sisd_opt_slice` on the global rayon pool.
+pub fn double_array_sisd_opt_rayon(array: &[u8]) -> Vec<u8> {
+    crate::double_bits_parallel_with(array, 1, double_array_sisd_opt_slice, None)
 }
 
-pub fn double_array_sisd_opt_64(array: &Vec<u8>) -> Vec<u8> {
+pub fn double_array_sisd_opt_64(array: &[u8]) -> Vec<u8> {
     let size = array.len();
     let mut doubled_array = vec![0; size * 2];
 
@@ -0,0 +1,246 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
+use rayon::ThreadPool;
+
+use crate::store::cache_size;
+use crate::{
+    double_bits_sequential, double_bits_slice_with, generate_array, halve_bits_slice, StoreMode,
+};
+
+// The parallel drivers split the input into chunks and the output into chunks twice as long,
+// pair them up and hand every pair to a slice kernel like `double_bits_slice`. The chunks
+// are disjoint, so no task ever touches another task's output, and the last pair just comes up short.
+
+/// Bytes per cache line, chunk boundaries fall on a multiple of it so tasks never share one.
+const CACHE_LINE: usize = 64;
+/// Chunks per thread, so a thread that is descheduled for a while does not hold up the others.
+const CHUNKS_PER_THREAD: usize = 4;
+/// L2 size to assume if the OS does not tell.
+const DEFAULT_L2_SIZE: usize = 1024 * 1024;
+
+/// Smallest input size the calibration tries, below it a single thread always wins, so
+/// smaller inputs never need the threshold and never start the calibration.
+pub(crate) const MIN_PARALLEL_THRESHOLD: usize = 64 * 1024;
+
+/// Input bytes from which `double_bits` goes parallel as set by `set_parallel_threshold`,
+/// zero until it is.
+static PARALLEL_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
+/// Result of the one calibration run.
+static CALIBRATED_THRESHOLD: OnceLock<usize> = OnceLock::new();
+
+lazy_static::lazy_static! {
+    /// Size of the L2 cache of the first CPU in bytes.
+    static ref L2_SIZE: usize = cache_size(2).unwrap_or(DEFAULT_L2_SIZE);
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    a / gcd(a, b) * b
+}
+
+/// Input bytes per chunk for `len` bytes on `threads` threads, a multiple of `granularity`
+/// and of the cache line. A chunk and its output fit into L2 together.
+fn chunk_len(len: usize, threads: usize, granularity: usize) -> usize {
+    let step = lcm(granularity.max(1), CACHE_LINE);
+    let len = usize::min(len.div_ceil(threads * CHUNKS_PER_THREAD), *L2_SIZE / 3);
+    len.div_ceil(step).max(1) * step
+}
+
+/// Fastest of a few runs of `f`.
+fn fastest(mut f: impl FnMut()) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+/// Smallest input size, doubling from 64 KiB up to 16 MiB, at which the parallel driver on the
+/// global pool beats the single threaded kernel. `usize::MAX` if it never does.
+fn measure_parallel_threshold() -> usize {
+    if rayon::current_num_threads() == 1 {
+        return usize::MAX;
+    }
+    let array = generate_array(16 * 1024 * 1024);
+    let mut len = MIN_PARALLEL_THRESHOLD;
+    while len <= array.len() {
+        let array = &array[..len];
+        let sequential = fastest(|| {
+            std::hint::black_box(double_bits_sequential(array));
+        });
+        let parallel = fastest(|| {
+            std::hint::black_box(double_bits_parallel(array, None));
+        });
+        if parallel < sequential {
+            return len;
+        }
+        len *= 2;
+    }
+    usize::MAX
+}
+
+/// Measure from which input size the parallel driver pays off, which `parallel_threshold`
+/// otherwise does at its first call. This takes up to a few hundred milliseconds, so call it
+/// early to keep that out of the first big `double_bits`. It only runs once per process: later
+/// calls, and calls from other threads while it runs, get the same result. A threshold set
+/// with `set_parallel_threshold` still wins over it.
+pub fn calibrate_parallel_threshold() -> usize {
+    *CALIBRATED_THRESHOLD.get_or_init(measure_parallel_threshold)
+}
+
+/// Input size from which `double_bits` runs in parallel, as set with `set_parallel_threshold`,
+/// or else measured by `calibrate_parallel_threshold` at the first call.
+pub fn parallel_threshold() -> usize {
+    match PARALLEL_THRESHOLD.load(Ordering::Relaxed) {
+        0 => calibrate_parallel_threshold(),
+        threshold => threshold,
+    }
+}
+
+/// Set the input size from which `double_bits` runs in parallel, instead of measuring it.
+/// `usize::MAX` keeps it single threaded, zero is treated as one.
+pub fn set_parallel_threshold(bytes: usize) {
+    PARALLEL_THRESHOLD.store(bytes.max(1), Ordering::Relaxed);
+}
+
+/// Run `kernel` on every pair of `input` chunks of `input_chunk` bytes and `output`
+/// chunks of `output_chunk` bytes, on `thread_pool` or on the global pool if there is none.
+fn for_each_chunk_pair<F>(
+    input: &[u8],
+    output: &mut [u8],
+    (input_chunk, output_chunk): (usize, usize),
+    kernel: F,
+    thread_pool: Option<&ThreadPool>,
+) where
+    F: Fn(&[u8], &mut [u8]) + Sync,
+{
+    let run = |output: &mut [u8]| {
+        input
+            .par_chunks(input_chunk)
+            .zip(output.par_chunks_mut(output_chunk))
+            .for_each(|(input, output)| kernel(input, output));
+    };
+    match thread_pool {
+        Some(thread_pool) => thread_pool.install(|| run(output)),
+        None => run(output),
+    }
+}
+
+/// Threads `for_each_chunk_pair` will run on.
+fn threads(thread_pool: Option<&ThreadPool>) -> usize {
+    thread_pool.map_or_else(rayon::current_num_threads, |thread_pool| {
+        thread_pool.current_num_threads()
+    })
+}
+
+/// Double up each bit of `array` in parallel with any slice kernel, which gets input chunks
+/// whose lengths are a multiple of `granularity`, except maybe the last one.
+/// Runs on `thread_pool`, or on the global pool if there is none.
+pub fn double_bits_parallel_with<F>(
+    array: &[u8],
+    granularity: usize,
+    kernel: F,
+    thread_pool: Option<&ThreadPool>,
+) -> Vec<u8>
+where
+    F: Fn(&[u8], &mut [u8]) + Sync,
+{
+    let mut doubled_array = vec![0; array.len() * 2];
+    double_bits_parallel_into_with(array, &mut doubled_array, granularity, kernel, thread_pool);
+    doubled_array
+}
+
+/// Same as `double_bits_parallel_with`, but writes into `doubled_array`, which is twice as long
+/// as `array`.
+pub fn double_bits_parallel_into_with<F>(
+    array: &[u8],
+    doubled_array: &mut [u8],
+    granularity: usize,
+    kernel: F,
+    thread_pool: Option<&ThreadPool>,
+) where
+    F: Fn(&[u8], &mut [u8]) + Sync,
+{
+    assert_eq!(array.len() * 2, doubled_array.len());
+    let chunk_len = chunk_len(array.len(), threads(thread_pool), granularity);
+    for_each_chunk_pair(
+        array,
+        doubled_array,
+        (chunk_len, chunk_len * 2),
+        kernel,
+        thread_pool,
+    );
+}
+
+/// Double up each bit of `array` into `doubled_array`, which is twice as long, on all
+/// threads of the global rayon pool from `parallel_threshold()` bytes on.
+pub fn double_bits_into(array: &[u8], doubled_array: &mut [u8]) {
+    if array.len() >= MIN_PARALLEL_THRESHOLD && array.len() >= parallel_threshold() {
+        let kernel = auto_store_kernel(doubled_array.len());
+        double_bits_parallel_into_with(array, doubled_array, CACHE_LINE, kernel, None);
+    } else {
+        double_bits_slice_with(array, doubled_array, StoreMode::Auto);
+    }
+}
+
+/// Halve `doubled_array` into `array`, which is half as long, in parallel with
+/// `halve_bits_slice`. Runs on `thread_pool`, or on the global pool if there is none.
+pub fn halve_bits_parallel_into(
+    doubled_array: &[u8],
+    array: &mut [u8],
+    thread_pool: Option<&ThreadPool>,
+) {
+    halve_bits_parallel_into_with(doubled_array, array, CACHE_LINE, thread_pool);
+}
+
+/// Same as `halve_bits_parallel_into`, with chunks of `array` whose lengths are a multiple of
+/// `granularity`, except maybe the last one.
+pub fn halve_bits_parallel_into_with(
+    doubled_array: &[u8],
+    array: &mut [u8],
+    granularity: usize,
+    thread_pool: Option<&ThreadPool>,
+) {
+    assert_eq!(array.len() * 2, doubled_array.len());
+    let chunk_len = chunk_len(array.len(), threads(thread_pool), granularity);
+    for_each_chunk_pair(
+        doubled_array,
+        array,
+        (chunk_len * 2, chunk_len),
+        halve_bits_slice,
+        thread_pool,
+    );
+}
+
+/// The fastest slice kernel for the chunks of an output of `output_len` bytes. Each chunk may
+/// fit into the last level cache when the whole output does not, so the kernel decides on
+/// streaming stores by the whole output, like `StoreMode::Auto` would for it.
+pub(crate) fn auto_store_kernel(output_len: usize) -> impl Fn(&[u8], &mut [u8]) + Sync {
+    let store = if StoreMode::Auto.streams(output_len) {
+        StoreMode::Streaming
+    } else {
+        StoreMode::Regular
+    };
+    move |array, doubled_array| double_bits_slice_with(array, doubled_array, store)
+}
+
+/// Double up each bit of `array` in parallel with the fastest slice kernel available,
+/// with streaming stores if the output does not fit into the last level cache.
+/// Runs on `thread_pool`, or on the global pool if there is none.
+pub fn double_bits_parallel(array: &[u8], thread_pool: Option<&ThreadPool>) -> Vec<u8> {
+    let kernel = auto_store_kernel(array.len() * 2);
+    double_bits_parallel_with(array, CACHE_LINE, kernel, thread_pool)
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::file::read_full;
+use crate::{double_bits_slice, ThreadConfig, DEFAULT_BLOCK_SIZE};
+
+// A reader thread fills blocks, worker threads double them and the calling thread writes them
+// out in order, so reading, doubling and writing all overlap. The blocks are allocated once and
+// go round in a circle through bounded channels: a stage that falls behind leaves the others
+// without free blocks, which is all the backpressure there is.
+
+/// Blocks in flight per worker, one being doubled and one waiting on either side of it.
+const BLOCKS_PER_WORKER: usize = 3;
+
+/// A block and its place in the stream.
+struct Block {
+    index: u64,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+/// Doubles everything `reader` yields into `writer` on a reader thread, a few worker threads
+/// and the calling thread, which writes.
+pub struct Pipeline<R, W> {
+    reader: R,
+    writer: W,
+    block_size: usize,
+    /// Also holds the number of workers, so `workers` and `thread_config` cannot disagree.
+    thread_config: ThreadConfig,
+}
+
+/// What a `Pipeline::run` moved and where the time went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub blocks: u64,
+    /// Wall clock time of the whole run.
+    pub elapsed: Duration,
+    /// Time the reader thread spent in `read`.
+    pub read_time: Duration,
+    /// Time the workers spent doubling, added up over all of them.
+    pub compute_time: Duration,
+    /// Time the writing thread spent in `write` and `flush`.
+    pub write_time: Duration,
+}
+
+impl PipelineStats {
+    /// Input bytes per second over the whole run.
+    pub fn throughput(&self) -> f64 {
+        self.input_bytes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl<R: Read + Send, W: Write> Pipeline<R, W> {
+    /// A pipeline with one worker per CPU and blocks of `DEFAULT_BLOCK_SIZE` input bytes.
+    pub fn new(reader: R, writer: W) -> Self {
+        Pipeline {
+            reader,
+            writer,
+            block_size: DEFAULT_BLOCK_SIZE,
+            thread_config: ThreadConfig::default(),
+        }
+    }
+
+    /// Threads that double blocks, at least one. Of this and `thread_config`, the one called
+    /// last sets the number of workers.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.thread_config.threads = Some(workers.max(1));
+        self
+    }
+
+    /// Input bytes per block, at least one. Only the last block may come up short.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Start as many workers as `thread_config` asks for, replacing any earlier `workers`, and
+    /// set each one up with `ThreadConfig::apply`. The reader and the writing thread are left as
+    /// they are.
+    pub fn thread_config(mut self, thread_config: ThreadConfig) -> Self {
+        self.thread_config = thread_config;
+        self
+    }
+
+    /// Double the whole input and flush the writer. If a worker cannot be set up, that fails
+    /// the run before anything is read or written. Otherwise an error of the reader wins over
+    /// one of the writer, and either stops the other stages.
+    pub fn run(self) -> io::Result<PipelineStats> {
+        let Pipeline {
+            mut reader,
+            mut writer,
+            block_size,
+            thread_config,
+        } = self;
+        let start = Instant::now();
+        let workers = thread_config.thread_count();
+        let blocks = workers * BLOCKS_PER_WORKER;
+        let (free_sender, free_receiver) = kanal::bounded(blocks);
+        let (input_sender, input_receiver) = kanal::bounded::<Block>(workers);
+        let (output_sender, output_receiver) = kanal::bounded::<Block>(workers);
+        for _ in 0..blocks {
+            free_sender
+                .send((Vec::new(), Vec::new()))
+                .expect("the channel holds every block");
+        }
+
+        let (read, worker_times, written) = thread::scope(|scope| -> io::Result<_> {
+            let (setup_sender, setup_receiver) = kanal::bounded::<io::Result<()>>(workers);
+            let workers: Vec<_> = (0..workers)
+                .map(|index| {
+                    let input_receiver = input_receiver.clone();
+                    let output_sender = output_sender.clone();
+                    let setup_sender = setup_sender.clone();
+                    let thread_config = &thread_config;
+                    scope.spawn(move || {
+                        let setup = thread_config.apply(index);
+                        let ready = setup.is_ok();
+                        setup_sender
+                            .send(setup)
+                            .expect("the channel has room for every worker");
+                        let mut compute_time = Duration::ZERO;
+                        if !ready {
+                            return compute_time;
+                        }
+                        while let Ok(mut block) = input_receiver.recv() {
+                            let compute_start = Instant::now();
+                            block.output.resize(block.input.len() * 2, 0);
+                            double_bits_slice(&block.input, &mut block.output);
+                            compute_time += compute_start.elapsed();
+                            if output_sender.send(block).is_err() {
+                                break;
+                            }
+                        }
+                        compute_time
+                    })
+                })
+                .collect();
+            // only the workers' ends keep the channels open, so they close once all are done
+            drop((input_receiver, output_sender));
+
+            // every worker is set up before the first read, the ones that are wait for blocks
+            // that never come if another one is not
+            for _ in 0..workers.len() {
+                setup_receiver
+                    .recv()
+                    .expect("every worker reports its setup")?;
+            }
+
+            let read = scope.spawn(move || -> io::Result<_> {
+                let mut input_bytes = 0;
+                let mut read_time = Duration::ZERO;
+                let mut index = 0;
+                // a closed channel means the writer gave up, its error is the one to report
+                loop {
+                    let Ok((mut input, output)) = free_receiver.recv() else {
+                        return Ok((input_bytes, index, read_time));
+                    };
+                    input.resize(block_size, 0);
+                    let read_start = Instant::now();
+                    let len = read_full(&mut reader, &mut input)?;
+                    read_time += read_start.elapsed();
+                    if len == 0 {
+                        return Ok((input_bytes, index, read_time));
+                    }
+                    input.truncate(len);
+                    input_bytes += len as u64;
+                    let block = Block {
+                        index,
+                        input,
+                        output,
+                    };
+                    if input_sender.send(block).is_err() {
+                        return Ok((input_bytes, index, read_time));
+                    }
+                    index += 1;
+                }
+            });
+
+            let written = (|| -> io::Result<_> {
+                let mut output_bytes = 0;
+                let mut write_time = Duration::ZERO;
+                // blocks that overtook the next one to write
+                let mut pending = BTreeMap::new();
+                let mut next = 0;
+                while let Ok(block) = output_receiver.recv() {
+                    pending.insert(block.index, block);
+                    while let Some(block) = pending.remove(&next) {
+                        let write_start = Instant::now();
+                        writer.write_all(&block.output)?;
+                        write_time += write_start.elapsed();
+                        output_bytes += block.output.len() as u64;
+                        next += 1;
+                        // fails only once the reader is done and needs no more blocks
+                        let _ = free_sender.send((block.input, block.output));
+                    }
+                }
+                let write_start = Instant::now();
+                writer.flush()?;
+                write_time += write_start.elapsed();
+                Ok((output_bytes, write_time))
+            })();
+            // let the reader and the workers see that nobody takes their blocks anymore
+            drop((output_receiver, free_sender));
+
+            let worker_times: Vec<Duration> = workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect();
+            Ok((read.join().unwrap(), worker_times, written))
+        })?;
+
+        let (input_bytes, blocks, read_time) = read?;
+        let (output_bytes, write_time) = written?;
+        Ok(PipelineStats {
+            input_bytes,
+            output_bytes,
+            blocks,
+            elapsed: start.elapsed(),
+            read_time,
+            compute_time: worker_times.into_iter().sum(),
+            write_time,
+        })
+    }
+}
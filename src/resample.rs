@@ -0,0 +1,223 @@
+use crate::bitmap::check_bitmap;
+use crate::multiply_bits;
+
+// Nearest neighbour resampling by `num / den`: output bit `j` is input bit `j * den / num`
+// (rounded down), and `bit_len` input bits become `resampled_len(bit_len, num, den)` output bits.
+// The mapping repeats every `den` input bits, so each position in that period
+// (a phase) always produces the same number of output bits.
+
+/// Most distinct byte phases for which per-phase lookup tables are built.
+const MAX_TABLE_PHASES: usize = 64;
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Number of bits `bit_len` bits are resampled to with a factor of `num / den`.
+pub fn resampled_len(bit_len: usize, num: usize, den: usize) -> usize {
+    (bit_len as u128 * num as u128).div_ceil(den as u128) as usize
+}
+
+/// Appends bits MSB-first to a byte vector.
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u128,
+    filled: usize,
+}
+
+impl BitWriter {
+    fn with_capacity(capacity: usize) -> Self {
+        BitWriter {
+            bytes: Vec::with_capacity(capacity),
+            acc: 0,
+            filled: 0,
+        }
+    }
+
+    /// Append the lowest `len` bits of `value`, `len` is at most 64.
+    fn push(&mut self, value: u64, len: usize) {
+        if len == 0 {
+            return;
+        }
+        self.acc = (self.acc << len) | value as u128;
+        self.filled += len;
+        while self.filled >= 8 {
+            self.filled -= 8;
+            self.bytes.push((self.acc >> self.filled) as u8);
+        }
+        self.acc &= (1 << self.filled) - 1;
+    }
+
+    /// Append `count` copies of `bit`.
+    fn push_run(&mut self, bit: u8, mut count: usize) {
+        while count > 0 {
+            let len = usize::min(count, 64);
+            let value = if bit == 1 { u64::MAX >> (64 - len) } else { 0 };
+            self.push(value, len);
+            count -= len;
+        }
+    }
+
+    /// Pad the last byte with zeros and return the bytes.
+    fn finish(mut self) -> Vec<u8> {
+        if self.filled > 0 {
+            self.bytes.push((self.acc << (8 - self.filled)) as u8);
+        }
+        self.bytes
+    }
+}
+
+/// A precomputed resampling schedule for one factor `num / den`.
+pub struct BitResampler {
+    num: usize,
+    den: usize,
+    /// output bits for every phase of the period
+    counts: Vec<usize>,
+    /// output bits and their count for every byte value at every byte phase,
+    /// only if every byte fits into one word and there are few enough phases
+    tables: Option<(Vec<u64>, Vec<u8>)>,
+}
+
+impl BitResampler {
+    pub fn new(num: usize, den: usize) -> Self {
+        assert!(num > 0 && den > 0);
+        let divisor = gcd(num, den);
+        let (num, den) = (num / divisor, den / divisor);
+
+        let counts: Vec<usize> = (0..den)
+            .map(|i| resampled_len(i + 1, num, den) - resampled_len(i, num, den))
+            .collect();
+
+        // bytes start at every multiple of gcd(8, den) within the period
+        let phase_step = gcd(8, den);
+        let phases = den / phase_step;
+        let max_byte_bits = 8 * num.div_ceil(den);
+        let tables = (den > 1 && phases <= MAX_TABLE_PHASES && max_byte_bits <= 64).then(|| {
+            let mut values = Vec::with_capacity(phases * 256);
+            let mut lens = Vec::with_capacity(phases * 256);
+            for phase in 0..phases {
+                for x in 0..=255u8 {
+                    let mut value = 0u64;
+                    let mut len = 0;
+                    for j in 0..8 {
+                        let bit = ((x >> (7 - j)) & 1) as u64;
+                        let count = counts[(phase * phase_step + j) % den];
+                        if count > 0 {
+                            value = (value << count) | (bit * (u64::MAX >> (64 - count)));
+                        }
+                        len += count;
+                    }
+                    values.push(value);
+                    lens.push(len as u8);
+                }
+            }
+            (values, lens)
+        });
+
+        BitResampler {
+            num,
+            den,
+            counts,
+            tables,
+        }
+    }
+
+    /// Resample the first `bit_len` bits of `array`, padding bits of the output are zero.
+    pub fn resample(&self, array: &[u8], bit_len: usize) -> Vec<u8> {
+        assert!(array.len() * 8 >= bit_len);
+        let out_len = resampled_len(bit_len, self.num, self.den);
+
+        // integer factors go straight to the N-fold kernels
+        if self.den == 1 {
+            let mut resampled = multiply_bits(&array[..bit_len.div_ceil(8)], self.num);
+            resampled.truncate(out_len.div_ceil(8));
+            if !out_len.is_multiple_of(8) {
+                *resampled.last_mut().unwrap() &= 0xff << (8 - out_len % 8);
+            }
+            return resampled;
+        }
+
+        let mut writer = BitWriter::with_capacity(out_len.div_ceil(8));
+        let full_bytes = bit_len / 8;
+        match &self.tables {
+            Some((values, lens)) => {
+                let phase_step = gcd(8, self.den);
+                let mut phase = 0;
+                for &x in &array[..full_bytes] {
+                    let index = phase / phase_step * 256 + x as usize;
+                    writer.push(values[index], lens[index] as usize);
+                    phase = (phase + 8) % self.den;
+                }
+            }
+            None => {
+                for i in 0..full_bytes * 8 {
+                    let bit = (array[i / 8] >> (7 - i % 8)) & 1;
+                    writer.push_run(bit, self.counts[i % self.den]);
+                }
+            }
+        }
+        for i in full_bytes * 8..bit_len {
+            let bit = (array[i / 8] >> (7 - i % 8)) & 1;
+            writer.push_run(bit, self.counts[i % self.den]);
+        }
+        writer.finish()
+    }
+}
+
+/// Reference resampling, one output bit at a time.
+pub fn resample_bits_naive(array: &[u8], bit_len: usize, num: usize, den: usize) -> Vec<u8> {
+    let out_len = resampled_len(bit_len, num, den);
+    let mut resampled = vec![0u8; out_len.div_ceil(8)];
+    for j in 0..out_len {
+        let i = (j as u128 * den as u128 / num as u128) as usize;
+        let bit = (array[i / 8] >> (7 - i % 8)) & 1;
+        resampled[j / 8] |= bit << (7 - j % 8);
+    }
+    resampled
+}
+
+/// Resample the first `bit_len` bits of `array` by `num / den` with nearest neighbour sampling.
+/// The output has `resampled_len(bit_len, num, den)` bits, padded with zeros to whole bytes.
+pub fn resample_bits(array: &[u8], bit_len: usize, num: usize, den: usize) -> Vec<u8> {
+    BitResampler::new(num, den).resample(array, bit_len)
+}
+
+/// Resample a 1bpp bitmap by `num_x / den_x` horizontally and `num_y / den_y` vertically with
+/// nearest neighbour sampling. The result is `resampled_len(width, num_x, den_x)` pixels wide and
+/// `resampled_len(height, num_y, den_y)` rows high. Returns it with its stride.
+pub fn resample_bitmap(
+    array: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    (num_x, den_x): (usize, usize),
+    (num_y, den_y): (usize, usize),
+) -> (Vec<u8>, usize) {
+    let row_bytes = width.div_ceil(8);
+    let dst_width = resampled_len(width, num_x, den_x);
+    let dst_height = resampled_len(height, num_y, den_y);
+    let dst_stride = dst_width.div_ceil(8);
+    check_bitmap(array, row_bytes, height, stride);
+
+    let resampler = BitResampler::new(num_x, den_x);
+    let mut resampled = vec![0; dst_stride * dst_height];
+    let mut previous = None;
+    for row in 0..dst_height {
+        let src_row = (row as u128 * den_y as u128 / num_y as u128) as usize;
+        let (done, rest) = resampled.split_at_mut(row * dst_stride);
+        let dst = &mut rest[..dst_stride];
+        if previous == Some(src_row) {
+            // same source row as the one before, so just copy it
+            dst.copy_from_slice(&done[(row - 1) * dst_stride..]);
+        } else {
+            let src = &array[src_row * stride..src_row * stride + row_bytes];
+            dst.copy_from_slice(&resampler.resample(src, width));
+        }
+        previous = Some(src_row);
+    }
+    (resampled, dst_stride)
+}
@@ -0,0 +1,217 @@
+// Converting between the MSB-first layout of the doubling functions and LSB-first
+// hardware is a bit reversal, either in every byte, in every word or across the whole buffer.
+// The SIMD versions look up the reversed nibbles with a byte shuffle, like the
+// nibble lookup doubling kernels, after reordering the bytes of each word.
+
+#[rustfmt::skip]
+const REVERSE_NIBBLE: [u8; 16] = [
+    0b0000, 0b1000, 0b0100, 0b1100,
+    0b0010, 0b1010, 0b0110, 0b1110,
+    0b0001, 0b1001, 0b0101, 0b1101,
+    0b0011, 0b1011, 0b0111, 0b1111,
+];
+
+/// Reversed nibble moved into the high half, for the low nibble of the input.
+const REVERSE_NIBBLE_HIGH: [u8; 16] = {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        table[i] = REVERSE_NIBBLE[i] << 4;
+        i += 1;
+    }
+    table
+};
+
+fn reverse_byte_lookup(x: u8) -> u8 {
+    REVERSE_NIBBLE_HIGH[(x & 0b1111) as usize] | REVERSE_NIBBLE[(x >> 4) as usize]
+}
+
+mod sealed {
+    /// Keeps `Word` to the sizes below, the SIMD kernels need `BYTES` to divide 16.
+    pub trait Sealed {}
+
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+/// A word size that `reverse_bits_in_words` can reverse in, only `u16`, `u32` and `u64`.
+pub trait Word: sealed::Sealed {
+    const BYTES: usize;
+}
+
+impl Word for u16 {
+    const BYTES: usize = 2;
+}
+
+impl Word for u32 {
+    const BYTES: usize = 4;
+}
+
+impl Word for u64 {
+    const BYTES: usize = 8;
+}
+
+/// Reverse the bits in each byte with the nibble table.
+pub fn reverse_bits_in_bytes_lookup(array: &[u8]) -> Vec<u8> {
+    array.iter().map(|&x| reverse_byte_lookup(x)).collect()
+}
+
+/// Reverse the bits in each `T` sized word with the nibble table.
+/// The words are big endian, so the first bit of a word ends up as its last one.
+pub fn reverse_bits_in_words_lookup<T: Word>(array: &[u8]) -> Vec<u8> {
+    assert_eq!(array.len() % T::BYTES, 0);
+    array
+        .chunks_exact(T::BYTES)
+        .flat_map(|word| word.iter().rev().map(|&x| reverse_byte_lookup(x)))
+        .collect()
+}
+
+/// Reverse the order of all bits in the buffer with the nibble table.
+pub fn reverse_bits_buffer_lookup(array: &[u8]) -> Vec<u8> {
+    array
+        .iter()
+        .rev()
+        .map(|&x| reverse_byte_lookup(x))
+        .collect()
+}
+
+/// Reverse the bits of 16 byte chunks, after shuffling their bytes with `byte_order`.
+/// The chunks are read back to front if `backwards` is set.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+fn reverse_chunks_ssse3(
+    chunks: &[u8],
+    byte_order: [u8; 16],
+    backwards: bool,
+    reversed: &mut Vec<u8>,
+) {
+    use std::arch::x86_64::*;
+
+    assert_eq!(chunks.len() % 16, 0);
+    let count = chunks.len() / 16;
+    unsafe {
+        let byte_order = _mm_loadu_si128(byte_order.as_ptr() as *const __m128i);
+        let lookup_lo = _mm_loadu_si128(REVERSE_NIBBLE_HIGH.as_ptr() as *const __m128i);
+        let lookup_hi = _mm_loadu_si128(REVERSE_NIBBLE.as_ptr() as *const __m128i);
+        let mask = _mm_set1_epi8(0x0f);
+
+        let start = reversed.len();
+        reversed.reserve(chunks.len());
+        let mut_ptr = reversed.as_mut_ptr().add(start);
+        for i in 0..count {
+            let chunk = if backwards { count - 1 - i } else { i };
+            let input = _mm_loadu_si128(chunks.as_ptr().add(chunk * 16) as *const __m128i);
+            let input = _mm_shuffle_epi8(input, byte_order);
+            // the low nibble becomes the reversed high nibble and vice versa
+            let input_lo = _mm_and_si128(input, mask);
+            let input_hi = _mm_and_si128(_mm_srli_epi16(input, 4), mask);
+            let output = _mm_or_si128(
+                _mm_shuffle_epi8(lookup_lo, input_lo),
+                _mm_shuffle_epi8(lookup_hi, input_hi),
+            );
+            _mm_storeu_si128(mut_ptr.add(i * 16) as *mut __m128i, output);
+        }
+        reversed.set_len(start + chunks.len());
+    }
+}
+
+/// Reverse the bits of 16 byte chunks, after shuffling their bytes with `byte_order`.
+/// The chunks are read back to front if `backwards` is set.
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+fn reverse_chunks_neon(
+    chunks: &[u8],
+    byte_order: [u8; 16],
+    backwards: bool,
+    reversed: &mut Vec<u8>,
+) {
+    use std::arch::aarch64::*;
+
+    assert_eq!(chunks.len() % 16, 0);
+    let count = chunks.len() / 16;
+    unsafe {
+        let byte_order = vld1q_u8(byte_order.as_ptr());
+        let lookup_lo = vld1q_u8(REVERSE_NIBBLE_HIGH.as_ptr());
+        let lookup_hi = vld1q_u8(REVERSE_NIBBLE.as_ptr());
+
+        let start = reversed.len();
+        reversed.reserve(chunks.len());
+        let mut_ptr = reversed.as_mut_ptr().add(start);
+        for i in 0..count {
+            let chunk = if backwards { count - 1 - i } else { i };
+            let input = vld1q_u8(chunks.as_ptr().add(chunk * 16));
+            let input = vqtbl1q_u8(input, byte_order);
+            // the low nibble becomes the reversed high nibble and vice versa
+            let input_lo = vbicq_u8(input, vdupq_n_u8(0b1111_0000));
+            let input_hi = vshrq_n_u8(input, 4);
+            let output = vorrq_u8(
+                vqtbl1q_u8(lookup_lo, input_lo),
+                vqtbl1q_u8(lookup_hi, input_hi),
+            );
+            vst1q_u8(mut_ptr.add(i * 16), output);
+        }
+        reversed.set_len(start + chunks.len());
+    }
+}
+
+/// Reverse the bits of whole chunks with the best kernel available into a new buffer with room
+/// for `capacity` bytes, `None` if there is no kernel.
+#[allow(unused_variables)]
+fn reverse_chunks_simd(
+    chunks: &[u8],
+    byte_order: [u8; 16],
+    backwards: bool,
+    capacity: usize,
+) -> Option<Vec<u8>> {
+    #[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+    {
+        let mut reversed = Vec::with_capacity(capacity);
+        reverse_chunks_ssse3(chunks, byte_order, backwards, &mut reversed);
+        return Some(reversed);
+    }
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    {
+        let mut reversed = Vec::with_capacity(capacity);
+        reverse_chunks_neon(chunks, byte_order, backwards, &mut reversed);
+        return Some(reversed);
+    }
+    #[allow(unreachable_code)]
+    None
+}
+
+/// Reverse the bits in each byte, MSB-first to LSB-first and back.
+pub fn reverse_bits_in_bytes(array: &[u8]) -> Vec<u8> {
+    let (chunks, rest) = array.split_at(array.len() - array.len() % 16);
+    let identity = std::array::from_fn(|i| i as u8);
+    let Some(mut reversed) = reverse_chunks_simd(chunks, identity, false, array.len()) else {
+        return reverse_bits_in_bytes_lookup(array);
+    };
+    reversed.extend(rest.iter().map(|&x| reverse_byte_lookup(x)));
+    reversed
+}
+
+/// Reverse the bits in each `T` sized big endian word, e.g. `reverse_bits_in_words::<u32>`.
+/// The length of the array has to be a multiple of the word size.
+pub fn reverse_bits_in_words<T: Word>(array: &[u8]) -> Vec<u8> {
+    assert_eq!(array.len() % T::BYTES, 0);
+    let (chunks, rest) = array.split_at(array.len() - array.len() % 16);
+    // reverse the byte order within every word of the chunk
+    let byte_order =
+        std::array::from_fn(|i| (i - i % T::BYTES + T::BYTES - 1 - i % T::BYTES) as u8);
+    let Some(mut reversed) = reverse_chunks_simd(chunks, byte_order, false, array.len()) else {
+        return reverse_bits_in_words_lookup::<T>(array);
+    };
+    reversed.extend(reverse_bits_in_words_lookup::<T>(rest));
+    reversed
+}
+
+/// Reverse the order of all bits in the buffer, the last bit becomes the first one.
+pub fn reverse_bits_buffer(array: &[u8]) -> Vec<u8> {
+    // the bytes that do not fill a chunk are at the front, so they end up at the back
+    let (rest, chunks) = array.split_at(array.len() % 16);
+    let backwards = std::array::from_fn(|i| 15 - i as u8);
+    let Some(mut reversed) = reverse_chunks_simd(chunks, backwards, true, array.len()) else {
+        return reverse_bits_buffer_lookup(array);
+    };
+    reversed.extend(rest.iter().rev().map(|&x| reverse_byte_lookup(x)));
+    reversed
+}
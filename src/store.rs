@@ -0,0 +1,100 @@
+// Streaming (non-temporal) stores write the output straight to memory instead of first reading
+// every cache line of it into the cache. That saves a third of the memory traffic once the
+// output is too big to stay in the cache anyway, but they need an aligned destination.
+
+/// Last level cache size to assume if the OS does not tell.
+const DEFAULT_LLC_SIZE: usize = 32 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    /// Size of the last level cache of the first CPU in bytes.
+    static ref LLC_SIZE: usize = data_caches()
+        .into_iter()
+        .max_by_key(|&(level, _)| level)
+        .map_or(DEFAULT_LLC_SIZE, |(_, size)| size);
+}
+
+/// Level and size in bytes of every data or unified cache of the first CPU, as Linux reports
+/// them. The `index` directories are numbered in no particular order of level.
+fn data_caches() -> Vec<(usize, usize)> {
+    let Ok(entries) = std::fs::read_dir("/sys/devices/system/cpu/cpu0/cache") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("index"))
+        .filter_map(|entry| {
+            let read = |name| std::fs::read_to_string(entry.path().join(name)).ok();
+            if read("type")?.trim() == "Instruction" {
+                return None;
+            }
+            let level = read("level")?.trim().parse().ok()?;
+            Some((level, parse_size(&read("size")?)?))
+        })
+        .collect()
+}
+
+/// `48K` or `32M` in bytes.
+fn parse_size(size: &str) -> Option<usize> {
+    let size = size.trim();
+    match size.strip_suffix('K') {
+        Some(kb) => kb.parse::<usize>().ok().map(|kb| kb * 1024),
+        None => size
+            .strip_suffix('M')?
+            .parse::<usize>()
+            .ok()
+            .map(|mb| mb << 20),
+    }
+}
+
+/// Size of the data or unified cache at `level` of the first CPU in bytes.
+pub(crate) fn cache_size(level: usize) -> Option<usize> {
+    data_caches()
+        .into_iter()
+        .find(|&(cache_level, _)| cache_level == level)
+        .map(|(_, size)| size)
+}
+
+/// How the SIMD kernels store their output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreMode {
+    Regular,
+    /// Non-temporal stores, unless the output cannot be aligned for them.
+    Streaming,
+    /// Streaming stores for outputs bigger than the last level cache.
+    Auto,
+}
+
+impl StoreMode {
+    /// Whether an output of `output_len` bytes is written with streaming stores.
+    pub fn streams(self, output_len: usize) -> bool {
+        match self {
+            StoreMode::Regular => false,
+            StoreMode::Streaming => true,
+            StoreMode::Auto => output_len > *LLC_SIZE,
+        }
+    }
+}
+
+/// Input bytes to double one by one before a kernel's vector loop, which stores `width` bytes
+/// at a time. For streaming stores they align the output to `width`, otherwise the input.
+/// Returns them and whether the loop can stream, which it cannot if the output is odd aligned.
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub(crate) fn prologue_len(
+    array: &[u8],
+    doubled_array: *const u8,
+    width: usize,
+    streaming: bool,
+) -> (usize, bool) {
+    if streaming {
+        let misalignment = doubled_array as usize % width;
+        // every input byte moves the output on by two
+        if misalignment.is_multiple_of(2) {
+            let pre_len = (width - misalignment) % width / 2;
+            return (usize::min(pre_len, array.len()), true);
+        }
+    }
+    (
+        usize::min(array.as_ptr().align_offset(width), array.len()),
+        false,
+    )
+}
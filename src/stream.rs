@@ -0,0 +1,507 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::file::read_full;
+use crate::formats::invalid_data;
+use crate::{double_bits_into, find_invalid_pair, halve_bits_slice};
+
+// Adapters that put doubling and halving into `std::io` pipelines. Input is gathered into
+// blocks, so the kernels (and from `parallel_threshold()` bytes on the parallel driver) always
+// get a big slice no matter how small the reads and writes around them are. Every byte doubles
+// on its own, so a block can end anywhere and a short block on `flush` or at the end of the
+// input is fine. Halving needs whole pairs of bytes, a byte without its partner waits for it.
+
+/// Input bytes per block unless one is given, big enough for the kernels to run at full speed.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Double `input` into `doubled`, which is resized to fit.
+fn double_block(input: &[u8], doubled: &mut Vec<u8>) {
+    doubled.resize(input.len() * 2, 0);
+    double_bits_into(input, doubled);
+}
+
+/// Halve `doubled`, which has an even length, into `halved`, which is resized to fit.
+fn halve_block(doubled: &[u8], halved: &mut Vec<u8>) {
+    halved.resize(doubled.len() / 2, 0);
+    halve_bits_slice(doubled, halved);
+}
+
+/// Hand `pending` from `written` on to `inner`, however many writes it takes, and clear it.
+/// What `inner` does not take before an error stays for the next try.
+fn write_pending(
+    inner: &mut impl Write,
+    pending: &mut Vec<u8>,
+    written: &mut usize,
+) -> io::Result<()> {
+    while *written < pending.len() {
+        match inner.write(&pending[*written..]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write the transformed bytes",
+                ))
+            }
+            Ok(len) => *written += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    pending.clear();
+    *written = 0;
+    Ok(())
+}
+
+/// Wraps a `Write` and writes every bit written to it twice.
+///
+/// Input is buffered until a block is full, so call `flush` (or `into_inner`) to push out a
+/// partial block. Dropping the writer writes what is left too, but ignores any error.
+pub struct DoublingWriter<W: Write> {
+    // only `None` once `into_inner` took it
+    inner: Option<W>,
+    block_size: usize,
+    /// Input waiting for its block to fill up.
+    block: Vec<u8>,
+    /// Output the inner writer has not taken yet, from `written` on.
+    doubled: Vec<u8>,
+    written: usize,
+}
+
+impl<W: Write> DoublingWriter<W> {
+    /// A writer with blocks of `DEFAULT_BLOCK_SIZE` input bytes.
+    pub fn new(inner: W) -> Self {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// A writer with blocks of `block_size` input bytes, at least one.
+    pub fn with_block_size(inner: W, block_size: usize) -> Self {
+        let block_size = block_size.max(1);
+        DoublingWriter {
+            inner: Some(inner),
+            block_size,
+            block: Vec::with_capacity(block_size),
+            doubled: Vec::with_capacity(block_size * 2),
+            written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Writing to the inner writer directly mixes undoubled bytes into the output.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Flush everything and return the inner writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Hand the doubled bytes to the inner writer, see `write_pending`.
+    fn write_doubled(&mut self) -> io::Result<()> {
+        write_pending(
+            self.inner.as_mut().unwrap(),
+            &mut self.doubled,
+            &mut self.written,
+        )
+    }
+
+    /// Double the buffered input, even if the block is not full, and write it out.
+    fn write_block(&mut self) -> io::Result<()> {
+        self.write_doubled()?;
+        if !self.block.is_empty() {
+            double_block(&self.block, &mut self.doubled);
+            self.block.clear();
+            self.write_doubled()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DoublingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // nothing new is taken while the last block is still on its way
+        self.write_doubled()?;
+
+        let len = if self.block.is_empty() && buf.len() >= self.block_size {
+            // a whole block at once skips the copy into the buffer
+            double_block(&buf[..self.block_size], &mut self.doubled);
+            self.block_size
+        } else {
+            let len = usize::min(buf.len(), self.block_size - self.block.len());
+            self.block.extend_from_slice(&buf[..len]);
+            if self.block.len() < self.block_size {
+                return Ok(len);
+            }
+            double_block(&self.block, &mut self.doubled);
+            self.block.clear();
+            len
+        };
+        // the input is taken either way, an error shows up again on the next call
+        let _ = self.write_doubled();
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for DoublingWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_block();
+        }
+    }
+}
+
+/// Wraps a `Read` and yields every bit read from it twice.
+pub struct DoublingReader<R: Read> {
+    inner: R,
+    /// Input of the current block, `block_size` bytes long.
+    block: Vec<u8>,
+    /// Output not read yet, from `pos` on.
+    doubled: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> DoublingReader<R> {
+    /// A reader with blocks of `DEFAULT_BLOCK_SIZE` input bytes.
+    pub fn new(inner: R) -> Self {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// A reader with blocks of `block_size` input bytes, at least one. The inner reader is read
+    /// until a block is full, only the last block at the end of the input may come up short.
+    pub fn with_block_size(inner: R, block_size: usize) -> Self {
+        let block_size = block_size.max(1);
+        DoublingReader {
+            inner,
+            block: vec![0; block_size],
+            doubled: Vec::with_capacity(block_size * 2),
+            pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly skips those bytes in the output.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Return the inner reader, doubled bytes that were not read yet are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DoublingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // room for a whole doubled block skips the copy out of the buffer
+        if self.pos == self.doubled.len() && buf.len() >= self.block.len() * 2 {
+            let len = read_full(&mut self.inner, &mut self.block)?;
+            double_bits_into(&self.block[..len], &mut buf[..len * 2]);
+            return Ok(len * 2);
+        }
+        let doubled = self.fill_buf()?;
+        let len = usize::min(doubled.len(), buf.len());
+        buf[..len].copy_from_slice(&doubled[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for DoublingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.doubled.len() {
+            let len = read_full(&mut self.inner, &mut self.block)?;
+            double_block(&self.block[..len], &mut self.doubled);
+            self.pos = 0;
+        }
+        Ok(&self.doubled[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = usize::min(self.pos + amt, self.doubled.len());
+    }
+}
+
+/// What the halving adapters do with a pair of bits that differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HalvingMode {
+    /// Fail with an `InvalidPair` error, after everything before the pair went through.
+    Strict,
+    /// Keep the first bit of the pair, like `halve_bits`.
+    Lenient,
+}
+
+/// A pair of bits that differ in a doubled stream, the error the halving adapters return in
+/// strict mode. It comes wrapped in an `io::Error` of kind `InvalidData`, get it back with
+/// `error.get_ref().and_then(|e| e.downcast_ref::<InvalidPair>())`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidPair {
+    /// Bits from the start of the doubled stream to the first bit of the pair.
+    pub bit_offset: u64,
+}
+
+impl fmt::Display for InvalidPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid bit pair at bit {} of the doubled stream",
+            self.bit_offset
+        )
+    }
+}
+
+impl Error for InvalidPair {}
+
+impl From<InvalidPair> for io::Error {
+    fn from(invalid_pair: InvalidPair) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, invalid_pair)
+    }
+}
+
+/// Bytes at the start of `doubled` that can be halved in `mode`, and the offset of the first
+/// invalid pair after them, relative to the bit offset `start` of `doubled` in the stream.
+fn valid_len(doubled: &[u8], mode: HalvingMode, start: u64) -> (usize, Option<InvalidPair>) {
+    match mode {
+        HalvingMode::Strict => match find_invalid_pair(doubled) {
+            Some(bit) => (
+                bit / 8,
+                Some(InvalidPair {
+                    bit_offset: start + bit as u64,
+                }),
+            ),
+            None => (doubled.len(), None),
+        },
+        HalvingMode::Lenient => (doubled.len(), None),
+    }
+}
+
+/// Wraps a `Write` and writes every pair of bits written to it as one bit.
+///
+/// Input is buffered until a block is full, so call `flush` (or `into_inner`) to push out a
+/// partial block. A byte whose partner has not been written yet stays behind even then.
+pub struct HalvingWriter<W: Write> {
+    // only `None` once `into_inner` took it
+    inner: Option<W>,
+    mode: HalvingMode,
+    block_size: usize,
+    /// Doubled input waiting for its block to fill up.
+    block: Vec<u8>,
+    /// Output the inner writer has not taken yet, from `written` on.
+    halved: Vec<u8>,
+    written: usize,
+    /// Bytes taken so far, for the offsets of invalid pairs.
+    offset: u64,
+}
+
+impl<W: Write> HalvingWriter<W> {
+    /// A writer with blocks of `DEFAULT_BLOCK_SIZE` doubled bytes.
+    pub fn new(inner: W, mode: HalvingMode) -> Self {
+        Self::with_block_size(inner, mode, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// A writer with blocks of `block_size` doubled bytes, rounded down to an even number and
+    /// at least two.
+    pub fn with_block_size(inner: W, mode: HalvingMode, block_size: usize) -> Self {
+        let block_size = block_size.max(2) & !1;
+        HalvingWriter {
+            inner: Some(inner),
+            mode,
+            block_size,
+            block: Vec::with_capacity(block_size),
+            halved: Vec::with_capacity(block_size / 2),
+            written: 0,
+            offset: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Writing to the inner writer directly mixes doubled bytes into the output.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Flush everything and return the inner writer. Fails if the input ended in the middle
+    /// of a pair of bytes.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        if !self.block.is_empty() {
+            return Err(invalid_data("doubled stream has an odd length"));
+        }
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Hand the halved bytes to the inner writer, see `write_pending`.
+    fn write_halved(&mut self) -> io::Result<()> {
+        write_pending(
+            self.inner.as_mut().unwrap(),
+            &mut self.halved,
+            &mut self.written,
+        )
+    }
+
+    /// Halve the whole pairs of bytes buffered, even if the block is not full, and write them out.
+    fn write_block(&mut self) -> io::Result<()> {
+        self.write_halved()?;
+        let even = self.block.len() & !1;
+        if even > 0 {
+            halve_block(&self.block[..even], &mut self.halved);
+            self.block.drain(..even);
+            self.write_halved()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HalvingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // nothing new is taken while the last block is still on its way
+        self.write_halved()?;
+
+        let len = usize::min(buf.len(), self.block_size - self.block.len());
+        // take the bytes up to an invalid pair, the next call starts with it and fails
+        let (len, invalid_pair) = valid_len(&buf[..len], self.mode, self.offset * 8);
+        if len == 0 {
+            return Err(invalid_pair.unwrap().into());
+        }
+        self.block.extend_from_slice(&buf[..len]);
+        self.offset += len as u64;
+        if self.block.len() == self.block_size {
+            halve_block(&self.block, &mut self.halved);
+            self.block.clear();
+            // the input is taken either way, an error shows up again on the next call
+            let _ = self.write_halved();
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for HalvingWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_block();
+        }
+    }
+}
+
+/// Wraps a `Read` of a doubled stream and yields every pair of bits in it as one bit.
+pub struct HalvingReader<R: Read> {
+    inner: R,
+    mode: HalvingMode,
+    /// Doubled input of the current block, `block_size` bytes long. A byte whose partner was
+    /// not read yet is moved to the front for the next block.
+    block: Vec<u8>,
+    carry: usize,
+    /// Output not read yet, from `pos` on.
+    halved: Vec<u8>,
+    pos: usize,
+    /// Bytes halved so far, for the offsets of invalid pairs.
+    offset: u64,
+    /// The invalid pair the output stops at, returned once everything before it was read.
+    invalid_pair: Option<InvalidPair>,
+}
+
+impl<R: Read> HalvingReader<R> {
+    /// A reader with blocks of `DEFAULT_BLOCK_SIZE` doubled bytes.
+    pub fn new(inner: R, mode: HalvingMode) -> Self {
+        Self::with_block_size(inner, mode, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// A reader with blocks of `block_size` doubled bytes, at least two. The inner reader is read
+    /// until a block is full, only the last block at the end of the input may come up short.
+    pub fn with_block_size(inner: R, mode: HalvingMode, block_size: usize) -> Self {
+        let block_size = block_size.max(2);
+        HalvingReader {
+            inner,
+            mode,
+            block: vec![0; block_size],
+            carry: 0,
+            halved: Vec::with_capacity(block_size / 2),
+            pos: 0,
+            offset: 0,
+            invalid_pair: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly skips those bytes in the output.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Return the inner reader, halved bytes that were not read yet are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for HalvingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let halved = self.fill_buf()?;
+        let len = usize::min(halved.len(), buf.len());
+        buf[..len].copy_from_slice(&halved[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for HalvingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // an odd block leaves a byte without output, read on until there is some or the end
+        while self.pos == self.halved.len() {
+            if let Some(invalid_pair) = self.invalid_pair {
+                return Err(invalid_pair.into());
+            }
+            let len = read_full(&mut self.inner, &mut self.block[self.carry..])?;
+            if len == 0 {
+                if self.carry > 0 {
+                    return Err(invalid_data("doubled stream has an odd length"));
+                }
+                break;
+            }
+            let filled = self.carry + len;
+            let even = filled & !1;
+            let (valid, invalid_pair) = valid_len(&self.block[..even], self.mode, self.offset * 8);
+            // only whole pairs of bytes before the invalid pair
+            let valid = valid & !1;
+            halve_block(&self.block[..valid], &mut self.halved);
+            self.pos = 0;
+            self.offset += valid as u64;
+            self.invalid_pair = invalid_pair;
+            self.block.copy_within(even..filled, 0);
+            self.carry = filled - even;
+        }
+        Ok(&self.halved[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = usize::min(self.pos + amt, self.halved.len());
+    }
+}
@@ -0,0 +1,95 @@
+use std::io;
+use std::thread;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+pub use thread_priority::{ThreadPriority, ThreadPriorityValue};
+
+// The parallel drivers run on whatever pool they are given, and the global rayon pool is
+// shared with everybody else in the process. A `ThreadConfig` builds a pool of its own, with
+// its threads at a given priority and pinned to given cores, and sets up the pipeline workers
+// the same way.
+
+/// How many threads to run on, at which priority and on which cores.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadConfig {
+    /// Number of threads, by default one per core in `cores`, or one per CPU if that is empty.
+    pub threads: Option<usize>,
+    /// Priority of every thread, the OS default if there is none. Priorities above the default
+    /// usually need privileges (`CAP_SYS_NICE` on Linux).
+    pub priority: Option<ThreadPriority>,
+    /// Cores to pin the threads to round robin, the n-th thread to the n-th core. Empty leaves
+    /// the threads to the scheduler. Pinning is only supported on Linux.
+    pub cores: Vec<usize>,
+}
+
+impl ThreadConfig {
+    /// Number of threads to start.
+    pub fn thread_count(&self) -> usize {
+        match (self.threads, self.cores.len()) {
+            (Some(threads), _) => threads.max(1),
+            (None, 0) => thread::available_parallelism().map_or(1, |threads| threads.get()),
+            (None, cores) => cores,
+        }
+    }
+
+    /// Set the priority and the core of the calling thread as the `index`-th thread.
+    pub fn apply(&self, index: usize) -> io::Result<()> {
+        if let Some(priority) = self.priority {
+            thread_priority::set_current_thread_priority(priority).map_err(priority_error)?;
+        }
+        if !self.cores.is_empty() {
+            pin_current_thread(self.cores[index % self.cores.len()])?;
+        }
+        Ok(())
+    }
+
+    /// A dedicated rayon pool with every thread set up by `apply`, for the `thread_pool`
+    /// argument of the parallel drivers. Fails if any thread could not be set up.
+    pub fn build_pool(&self) -> io::Result<ThreadPool> {
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(self.thread_count())
+            .thread_name(|index| format!("neon_test-{index}"))
+            .build()
+            .map_err(io::Error::other)?;
+        // runs once on every thread of the pool
+        thread_pool
+            .broadcast(|context| self.apply(context.index()))
+            .into_iter()
+            .collect::<io::Result<()>>()?;
+        Ok(thread_pool)
+    }
+}
+
+fn priority_error(error: thread_priority::Error) -> io::Error {
+    match error {
+        thread_priority::Error::OS(code) => io::Error::from_raw_os_error(code),
+        error => io::Error::other(format!("cannot set the thread priority: {error:?}")),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_current_thread(core: usize) -> io::Result<()> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("core {core} is out of range"),
+        ));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        // zero is the calling thread
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_core: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads to cores is only supported on Linux",
+    ))
+}
@@ -0,0 +1,219 @@
+use std::simd::{cmp::SimdPartialOrd, Simd};
+
+// Bit matrices use the same MSB-first convention as the doubling functions:
+// column 0 of a row is the most significant bit of its first byte.
+// An 8x8 block packed into a u64 stores row 0 in the most significant byte.
+
+/// Reference transpose of an 8x8 bit matrix, one bit at a time.
+pub fn transpose_8x8_naive(x: u64) -> u64 {
+    let mut transposed = 0u64;
+    for row in 0..8 {
+        for col in 0..8 {
+            let bit = (x >> (63 - (row * 8 + col))) & 1;
+            transposed |= bit << (63 - (col * 8 + row));
+        }
+    }
+    transposed
+}
+
+/// Transpose an 8x8 bit matrix packed into a u64.
+/// Swaps 1x1, then 2x2, then 4x4 blocks across the diagonal.
+pub fn transpose_8x8(x: u64) -> u64 {
+    let t = (x ^ (x >> 7)) & 0x00AA_00AA_00AA_00AA;
+    let x = x ^ t ^ (t << 7);
+    let t = (x ^ (x >> 14)) & 0x0000_CCCC_0000_CCCC;
+    let x = x ^ t ^ (t << 14);
+    let t = (x ^ (x >> 28)) & 0x0000_0000_F0F0_F0F0;
+    x ^ t ^ (t << 28)
+}
+
+/// In-place transpose of a square bit matrix with one row per element.
+/// Swaps the off-diagonal blocks of half the size, then recurses on quarters.
+macro_rules! transpose_square {
+    ($name:ident, $t:ty, $n:expr) => {
+        #[doc = concat!("Transpose a ", stringify!($n), "x", stringify!($n), " bit matrix in place, one row per `", stringify!($t), "`.")]
+        pub fn $name(matrix: &mut [$t; $n]) {
+            let mut j = $n / 2;
+            let mut mask: $t = <$t>::MAX >> j;
+            while j != 0 {
+                let mut k = 0;
+                while k < $n {
+                    // low bits of row k are the right block, high bits of row k + j the left block
+                    let t = (matrix[k] ^ (matrix[k + j] >> j)) & mask;
+                    matrix[k] ^= t;
+                    matrix[k + j] ^= t << j;
+                    k = (k + j + 1) & !j;
+                }
+                j >>= 1;
+                mask ^= mask << j;
+            }
+        }
+    };
+}
+
+transpose_square!(transpose_16x16, u16, 16);
+transpose_square!(transpose_32x32, u32, 32);
+transpose_square!(transpose_64x64, u64, 64);
+
+/// Reference transpose of a `rows` by `row_bytes * 8` bit matrix.
+/// The output has `row_bytes * 8` rows of `rows.div_ceil(8)` bytes each, padding bits are zero.
+pub fn transpose_bits_naive(array: &[u8], rows: usize, row_bytes: usize) -> Vec<u8> {
+    assert_eq!(array.len(), rows * row_bytes);
+    let out_row_bytes = rows.div_ceil(8);
+    let mut transposed = vec![0; row_bytes * 8 * out_row_bytes];
+
+    for row in 0..rows {
+        for col in 0..row_bytes * 8 {
+            let bit = (array[row * row_bytes + col / 8] >> (7 - col % 8)) & 1;
+            transposed[col * out_row_bytes + row / 8] |= bit << (7 - row % 8);
+        }
+    }
+    transposed
+}
+
+/// Transposes the rows from `first_row` onwards in 8x8 blocks.
+/// `first_row` has to be a multiple of 8.
+fn transpose_rows_swar(
+    array: &[u8],
+    rows: usize,
+    row_bytes: usize,
+    first_row: usize,
+    transposed: &mut [u8],
+) {
+    let out_row_bytes = rows.div_ceil(8);
+
+    for row_block in (first_row..rows).step_by(8) {
+        let block_rows = usize::min(8, rows - row_block);
+        for col_byte in 0..row_bytes {
+            let mut block = [0u8; 8];
+            for r in 0..block_rows {
+                block[r] = array[(row_block + r) * row_bytes + col_byte];
+            }
+            let block = transpose_8x8(u64::from_be_bytes(block)).to_be_bytes();
+            for c in 0..8 {
+                transposed[(col_byte * 8 + c) * out_row_bytes + row_block / 8] = block[c];
+            }
+        }
+    }
+}
+
+/// Transpose a `rows` by `row_bytes * 8` bit matrix using 8x8 SWAR blocks.
+/// Same layout as `transpose_bits_naive`.
+pub fn transpose_bits_swar(array: &[u8], rows: usize, row_bytes: usize) -> Vec<u8> {
+    assert_eq!(array.len(), rows * row_bytes);
+    let mut transposed = vec![0; row_bytes * 8 * rows.div_ceil(8)];
+    transpose_rows_swar(array, rows, row_bytes, 0, &mut transposed);
+    transposed
+}
+
+/// Transpose a `rows` by `row_bytes * 8` bit matrix, 16 rows at a time.
+/// Same layout as `transpose_bits_naive`.
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+pub fn transpose_bits_sse2(array: &[u8], rows: usize, row_bytes: usize) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    assert_eq!(array.len(), rows * row_bytes);
+    let out_row_bytes = rows.div_ceil(8);
+    let mut transposed = vec![0; row_bytes * 8 * out_row_bytes];
+    let full_rows = rows - rows % 16;
+
+    for row_block in (0..full_rows).step_by(16) {
+        for col_byte in 0..row_bytes {
+            // reversed, so that the first row ends up in the most significant bit of the mask
+            let mut lanes = [0u8; 16];
+            for r in 0..16 {
+                lanes[15 - r] = array[(row_block + r) * row_bytes + col_byte];
+            }
+            unsafe {
+                let mut input = _mm_loadu_si128(lanes.as_ptr() as *const __m128i);
+                for c in 0..8 {
+                    // movemask collects the top bit of every byte, i.e. one column
+                    let column = _mm_movemask_epi8(input) as u16;
+                    let start = (col_byte * 8 + c) * out_row_bytes + row_block / 8;
+                    transposed[start..start + 2].copy_from_slice(&column.to_be_bytes());
+                    // move the next column into the top bit
+                    input = _mm_add_epi8(input, input);
+                }
+            }
+        }
+    }
+
+    transpose_rows_swar(array, rows, row_bytes, full_rows, &mut transposed);
+    transposed
+}
+
+/// Transpose a `rows` by `row_bytes * 8` bit matrix, 32 rows at a time.
+/// Same layout as `transpose_bits_naive`.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub fn transpose_bits_avx2(array: &[u8], rows: usize, row_bytes: usize) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    assert_eq!(array.len(), rows * row_bytes);
+    let out_row_bytes = rows.div_ceil(8);
+    let mut transposed = vec![0; row_bytes * 8 * out_row_bytes];
+    let full_rows = rows - rows % 32;
+
+    for row_block in (0..full_rows).step_by(32) {
+        for col_byte in 0..row_bytes {
+            let mut lanes = [0u8; 32];
+            for r in 0..32 {
+                lanes[31 - r] = array[(row_block + r) * row_bytes + col_byte];
+            }
+            unsafe {
+                let mut input = _mm256_loadu_si256(lanes.as_ptr() as *const __m256i);
+                for c in 0..8 {
+                    let column = _mm256_movemask_epi8(input) as u32;
+                    let start = (col_byte * 8 + c) * out_row_bytes + row_block / 8;
+                    transposed[start..start + 4].copy_from_slice(&column.to_be_bytes());
+                    input = _mm256_add_epi8(input, input);
+                }
+            }
+        }
+    }
+
+    transpose_rows_swar(array, rows, row_bytes, full_rows, &mut transposed);
+    transposed
+}
+
+/// Transpose a `rows` by `row_bytes * 8` bit matrix with `std::simd`, 16 rows at a time.
+/// Same layout as `transpose_bits_naive`.
+pub fn transpose_bits_simd(array: &[u8], rows: usize, row_bytes: usize) -> Vec<u8> {
+    assert_eq!(array.len(), rows * row_bytes);
+    let out_row_bytes = rows.div_ceil(8);
+    let mut transposed = vec![0; row_bytes * 8 * out_row_bytes];
+    let full_rows = rows - rows % 16;
+
+    for row_block in (0..full_rows).step_by(16) {
+        for col_byte in 0..row_bytes {
+            let mut lanes = [0u8; 16];
+            for r in 0..16 {
+                lanes[15 - r] = array[(row_block + r) * row_bytes + col_byte];
+            }
+            let mut input = Simd::<u8, 16>::from_array(lanes);
+            for c in 0..8 {
+                let column = input.simd_ge(Simd::splat(0x80)).to_bitmask() as u16;
+                let start = (col_byte * 8 + c) * out_row_bytes + row_block / 8;
+                transposed[start..start + 2].copy_from_slice(&column.to_be_bytes());
+                input <<= 1;
+            }
+        }
+    }
+
+    transpose_rows_swar(array, rows, row_bytes, full_rows, &mut transposed);
+    transposed
+}
+
+/// Transpose a `rows` by `row_bytes * 8` bit matrix with the fastest implementation available.
+/// Same layout as `transpose_bits_naive`.
+pub fn transpose_bits(array: &[u8], rows: usize, row_bytes: usize) -> Vec<u8> {
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    return transpose_bits_avx2(array, rows, row_bytes);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "sse2",
+        not(target_feature = "avx2")
+    ))]
+    return transpose_bits_sse2(array, rows, row_bytes);
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+    return transpose_bits_simd(array, rows, row_bytes);
+}
@@ -0,0 +1,265 @@
+// Unpacking turns every bit into a whole byte, MSB-first, which is `true_value` for a set bit and
+// zero otherwise. Packing goes back and treats every non-zero byte as a set bit, so both
+// 0x01 (bool) and 0xff (blend mask) bytes round trip.
+
+/// Selects bit `7 - i` of the broadcast byte in byte `i`, counted from the most significant one.
+const BIT_SELECT: u64 = 0x8040201008040201;
+/// One in the lowest bit of every byte.
+const ONES: u64 = 0x0101010101010101;
+
+/// Unpack one byte into eight, with the broadcast multiply from `double_array_ben`.
+fn unpack_byte(x: u8, true_value: u8) -> [u8; 8] {
+    let bits = (x as u64 * ONES) & BIT_SELECT;
+    // every byte is at most 0x80, adding 0x7f carries into its top bit exactly if it is set
+    let bits = ((bits + 0x7f * ONES) >> 7) & ONES;
+    (bits * true_value as u64).to_be_bytes()
+}
+
+/// Pack eight bytes into the bits of one, non-zero bytes are set bits.
+fn pack_byte(bytes: [u8; 8]) -> u8 {
+    let x = u64::from_be_bytes(bytes);
+    // move any set bit of a byte into its lowest bit
+    let x = x | (x >> 4);
+    let x = x | (x >> 2);
+    let x = (x | (x >> 1)) & ONES;
+    // every byte's bit is shifted into the top byte at its position, without any carries
+    (x.wrapping_mul(0x0102040810204080) >> 56) as u8
+}
+
+fn pack_rest(rest: &[u8]) -> Option<u8> {
+    (!rest.is_empty()).then(|| {
+        let mut bytes = [0; 8];
+        bytes[..rest.len()].copy_from_slice(rest);
+        pack_byte(bytes)
+    })
+}
+
+pub fn unpack_bits_sisd(array: &[u8], true_value: u8) -> Vec<u8> {
+    array
+        .iter()
+        .flat_map(|&x| unpack_byte(x, true_value))
+        .collect()
+}
+
+pub fn pack_bits_sisd(array: &[u8]) -> Vec<u8> {
+    let chunks = array.chunks_exact(8);
+    let rest = pack_rest(chunks.remainder());
+    chunks
+        .map(|chunk| pack_byte(chunk.try_into().unwrap()))
+        .chain(rest)
+        .collect()
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub fn unpack_bits_ssse3(array: &[u8], true_value: u8) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    let mut unpacked: Vec<u8> = Vec::with_capacity(array.len() * 8);
+    let chunks = array.chunks_exact(2);
+    let rest = chunks.remainder();
+    unsafe {
+        // two input bytes per vector, each one broadcast to eight lanes
+        let broadcast = _mm_setr_epi8(0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1);
+        let select = _mm_set1_epi64x(BIT_SELECT.swap_bytes() as i64);
+        let true_value = _mm_set1_epi8(true_value as i8);
+
+        let mut_ptr = unpacked.as_mut_ptr();
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm_set1_epi16(u16::from_le_bytes([chunk[0], chunk[1]]) as i16);
+            let bits = _mm_and_si128(_mm_shuffle_epi8(input, broadcast), select);
+            // set bits become 0xff
+            let mask = _mm_cmpeq_epi8(bits, select);
+            let output = _mm_and_si128(mask, true_value);
+            _mm_storeu_si128(mut_ptr.add(i * 16) as *mut __m128i, output);
+        }
+        unpacked.set_len(array.len() / 2 * 16);
+    }
+    for &x in rest {
+        unpacked.extend(unpack_byte(x, true_value));
+    }
+    unpacked
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "ssse3"))]
+pub fn pack_bits_ssse3(array: &[u8]) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    let mut packed: Vec<u8> = Vec::with_capacity(array.len().div_ceil(8));
+    let chunks = array.chunks_exact(16);
+    let rest = chunks.remainder();
+    unsafe {
+        // movemask puts the first byte into the lowest bit, so reverse each half first
+        let reverse = _mm_setr_epi8(7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8);
+        let zero = _mm_setzero_si128();
+
+        let mut_ptr = packed.as_mut_ptr();
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let input = _mm_shuffle_epi8(input, reverse);
+            let zeros = _mm_movemask_epi8(_mm_cmpeq_epi8(input, zero)) as u16;
+            std::ptr::write_unaligned(mut_ptr.add(i * 2) as *mut [u8; 2], (!zeros).to_le_bytes());
+        }
+        packed.set_len(array.len() / 16 * 2);
+    }
+    packed.extend(pack_bits_sisd(rest));
+    packed
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub fn unpack_bits_avx2(array: &[u8], true_value: u8) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    let mut unpacked: Vec<u8> = Vec::with_capacity(array.len() * 8);
+    let chunks = array.chunks_exact(4);
+    let rest = chunks.remainder();
+    unsafe {
+        // the shuffle stays within 128-bit lanes, so the upper lane takes bytes 2 and 3
+        #[rustfmt::skip]
+        let broadcast = _mm256_setr_epi8(
+            0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1,
+            2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3,
+        );
+        let select = _mm256_set1_epi64x(BIT_SELECT.swap_bytes() as i64);
+        let true_value = _mm256_set1_epi8(true_value as i8);
+
+        let mut_ptr = unpacked.as_mut_ptr();
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm256_set1_epi32(i32::from_le_bytes(chunk.try_into().unwrap()));
+            let bits = _mm256_and_si256(_mm256_shuffle_epi8(input, broadcast), select);
+            // set bits become 0xff
+            let mask = _mm256_cmpeq_epi8(bits, select);
+            let output = _mm256_and_si256(mask, true_value);
+            _mm256_storeu_si256(mut_ptr.add(i * 32) as *mut __m256i, output);
+        }
+        unpacked.set_len(array.len() / 4 * 32);
+    }
+    for &x in rest {
+        unpacked.extend(unpack_byte(x, true_value));
+    }
+    unpacked
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub fn pack_bits_avx2(array: &[u8]) -> Vec<u8> {
+    use std::arch::x86_64::*;
+
+    let mut packed: Vec<u8> = Vec::with_capacity(array.len().div_ceil(8));
+    let chunks = array.chunks_exact(32);
+    let rest = chunks.remainder();
+    unsafe {
+        // movemask puts the first byte into the lowest bit, so reverse each eight bytes first
+        #[rustfmt::skip]
+        let reverse = _mm256_setr_epi8(
+            7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8,
+            7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8,
+        );
+        let zero = _mm256_setzero_si256();
+
+        let mut_ptr = packed.as_mut_ptr();
+        for (i, chunk) in chunks.enumerate() {
+            let input = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            let input = _mm256_shuffle_epi8(input, reverse);
+            let zeros = _mm256_movemask_epi8(_mm256_cmpeq_epi8(input, zero)) as u32;
+            std::ptr::write_unaligned(mut_ptr.add(i * 4) as *mut [u8; 4], (!zeros).to_le_bytes());
+        }
+        packed.set_len(array.len() / 32 * 4);
+    }
+    packed.extend(pack_bits_sisd(rest));
+    packed
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+pub fn unpack_bits_neon(array: &[u8], true_value: u8) -> Vec<u8> {
+    use std::arch::aarch64::*;
+
+    let mut unpacked: Vec<u8> = Vec::with_capacity(array.len() * 8);
+    let chunks = array.chunks_exact(2);
+    let rest = chunks.remainder();
+    unsafe {
+        let select = vreinterpretq_u8_u64(vdupq_n_u64(BIT_SELECT.swap_bytes()));
+        let true_value = vdupq_n_u8(true_value);
+
+        let mut_ptr = unpacked.as_mut_ptr();
+        for (i, chunk) in chunks.enumerate() {
+            let input = vcombine_u8(vdup_n_u8(chunk[0]), vdup_n_u8(chunk[1]));
+            // set bits become 0xff
+            let mask = vtstq_u8(input, select);
+            vst1q_u8(mut_ptr.add(i * 16), vandq_u8(mask, true_value));
+        }
+        unpacked.set_len(array.len() / 2 * 16);
+    }
+    for &x in rest {
+        unpacked.extend(unpack_byte(x, true_value));
+    }
+    unpacked
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+pub fn pack_bits_neon(array: &[u8]) -> Vec<u8> {
+    use std::arch::aarch64::*;
+
+    let mut packed: Vec<u8> = Vec::with_capacity(array.len().div_ceil(8));
+    let chunks = array.chunks_exact(16);
+    let rest = chunks.remainder();
+    unsafe {
+        // there is no movemask, so weigh every byte with its bit and add them up
+        let select = vreinterpretq_u8_u64(vdupq_n_u64(BIT_SELECT.swap_bytes()));
+
+        let mut_ptr = packed.as_mut_ptr();
+        for (i, chunk) in chunks.enumerate() {
+            let input = vld1q_u8(chunk.as_ptr());
+            let bits = vandq_u8(vtstq_u8(input, input), select);
+            *mut_ptr.add(i * 2) = vaddv_u8(vget_low_u8(bits));
+            *mut_ptr.add(i * 2 + 1) = vaddv_u8(vget_high_u8(bits));
+        }
+        packed.set_len(array.len() / 16 * 2);
+    }
+    packed.extend(pack_bits_sisd(rest));
+    packed
+}
+
+/// Unpack every bit into a byte, `true_value` for set bits and zero otherwise.
+/// The output is eight times as long as the input.
+pub fn unpack_bits(array: &[u8], true_value: u8) -> Vec<u8> {
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    return unpack_bits_avx2(array, true_value);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "ssse3",
+        not(target_feature = "avx2")
+    ))]
+    return unpack_bits_ssse3(array, true_value);
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    return unpack_bits_neon(array, true_value);
+    #[allow(unreachable_code)]
+    unpack_bits_sisd(array, true_value)
+}
+
+/// Pack bytes into bits, every non-zero byte is a set bit.
+/// A last partial byte is padded with zero bits.
+pub fn pack_bits(array: &[u8]) -> Vec<u8> {
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    return pack_bits_avx2(array);
+    #[cfg(all(
+        target_arch = "x86_64",
+        target_feature = "ssse3",
+        not(target_feature = "avx2")
+    ))]
+    return pack_bits_ssse3(array);
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    return pack_bits_neon(array);
+    #[allow(unreachable_code)]
+    pack_bits_sisd(array)
+}
+
+/// Unpack every bit into a `bool`.
+pub fn unpack_bools(array: &[u8]) -> Vec<bool> {
+    unpack_bits(array, 1).into_iter().map(|x| x == 1).collect()
+}
+
+/// Pack `bool`s into bits, see `pack_bits`.
+pub fn pack_bools(array: &[bool]) -> Vec<u8> {
+    // a bool is a byte that is either 0 or 1
+    let bytes = unsafe { std::slice::from_raw_parts(array.as_ptr() as *const u8, array.len()) };
+    pack_bits(bytes)
+}